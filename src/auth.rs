@@ -0,0 +1,412 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use curl;
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use rustc_serialize::json::{Json, BuilderError};
+use url::{form_urlencoded, ParseError, Url};
+
+use {timeout_millis, Client, Method, Response};
+use client;
+use token::{AuthToken, ExpiringToken, TokenCache, TokenProvider};
+
+const GOOGLE_TOKEN_URI: &'static str = "https://oauth2.googleapis.com/token";
+const JWT_BEARER_GRANT: &'static str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+const DATABASE_SCOPES:  &'static str = "https://www.googleapis.com/auth/firebase.database \
+                                        https://www.googleapis.com/auth/userinfo.email";
+const CUSTOM_TOKEN_AUD: &'static str = "https://identitytoolkit.googleapis.com/\
+                                        google.identity.identitytoolkit.v1.IdentityToolkit";
+/// Google never hands out access tokens that live longer than an hour,
+/// and custom tokens may not live longer than that either.
+const TOKEN_LIFETIME:   u64 = 3600;
+/// How long a token exchange may take by default, it only moves a few bytes.
+const TOKEN_TIMEOUT:    u64 = 30;
+const MAX_UID_LENGTH:   usize = 128;
+/// Claims of an ID token that developer claims may not override.
+const RESERVED_CLAIMS:  [&'static str; 16] = [
+    "acr", "amr", "at_hash", "aud", "auth_time", "azp", "cnf", "c_hash",
+    "exp", "firebase", "iat", "iss", "jti", "nbf", "nonce", "sub",
+];
+
+/// Where the access token of a ```ServiceAccountCredentials``` is put on every request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenLocation {
+    /// As the ```access_token``` query parameter.
+    QueryParam,
+    /// As an ```Authorization: Bearer <token>``` header.
+    BearerHeader,
+}
+
+/// Credentials of a Google service account, loaded from the JSON key file
+/// that can be downloaded from the Firebase console.
+///
+/// The credentials sign a JWT with the account's private key and exchange it for
+/// an OAuth2 access token, which is cached and refreshed shortly before it expires.
+/// The exchange goes through the proxy and CA bundle of a ```Client```, the one
+/// of the reference created by ```Firebase::with_service_account``` unless another
+/// one is set with ```with_client```.
+///
+/// # Examples
+/// ```no_run
+/// # use firebase::*;
+/// let credentials = ServiceAccountCredentials::from_file("service-account.json").unwrap();
+/// let fb = Firebase::with_service_account("https://myfb.firebaseio.com", credentials).unwrap();
+/// let users = fb.at("/users").unwrap().get();
+/// ```
+pub struct ServiceAccountCredentials {
+    project_id:     Option<String>,
+    client_email:   String,
+    private_key_id: Option<String>,
+    key_pair:       RsaKeyPair,
+    token_uri:      String,
+    location:       TokenLocation,
+    client:         Option<Arc<Client>>,
+    timeout:        Duration,
+    cache:          TokenCache,
+}
+
+impl fmt::Debug for ServiceAccountCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServiceAccountCredentials")
+         .field("project_id", &self.project_id)
+         .field("client_email", &self.client_email)
+         .field("token_uri", &self.token_uri)
+         .field("location", &self.location)
+         .field("timeout", &self.timeout)
+         .finish()
+    }
+}
+
+impl ServiceAccountCredentials {
+    /// Loads the credentials from a service account JSON key file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, AuthError> {
+        let mut contents = String::new();
+        let mut file = try!( File::open(path).map_err(AuthError::Io) );
+        try!( file.read_to_string(&mut contents).map_err(AuthError::Io) );
+        ServiceAccountCredentials::from_json(&contents)
+    }
+
+    /// Loads the credentials from the contents of a service account JSON key file.
+    pub fn from_json(key_file: &str) -> Result<Self, AuthError> {
+        let key_file = try!( Json::from_str(key_file).map_err(AuthError::NotJSON) );
+
+        let client_email = try!( required_field(&key_file, "client_email") );
+        let private_key = try!( required_field(&key_file, "private_key") );
+        let der = try!( pem_to_der(&private_key).ok_or(AuthError::InvalidPrivateKey) );
+        let key_pair = try!( RsaKeyPair::from_pkcs8(&der).map_err(|_| AuthError::InvalidPrivateKey) );
+
+        Ok(ServiceAccountCredentials {
+            project_id:     optional_field(&key_file, "project_id"),
+            client_email:   client_email,
+            private_key_id: optional_field(&key_file, "private_key_id"),
+            key_pair:       key_pair,
+            token_uri:      optional_field(&key_file, "token_uri")
+                                .unwrap_or(GOOGLE_TOKEN_URI.to_string()),
+            location:       TokenLocation::BearerHeader,
+            client:         None,
+            timeout:        Duration::from_secs(TOKEN_TIMEOUT),
+            cache:          TokenCache::new(),
+        })
+    }
+
+    /// Exchanges the signed JWT at ```uri``` instead of the ```token_uri```
+    /// of the key file, e.g. to talk to a local stub.
+    pub fn token_uri(mut self, uri: &str) -> Self {
+        self.token_uri = uri.to_string();
+        self
+    }
+
+    /// Sets where the access token is put on every request,
+    /// an ```Authorization: Bearer``` header is used by default.
+    pub fn attach_as(mut self, location: TokenLocation) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Exchanges tokens through ```client```, e.g. to use its proxy. It can
+    /// be shared with the references using the credentials.
+    pub fn with_client<C: Into<Arc<Client>>>(mut self, client: C) -> Self {
+        self.client = Some(client.into());
+        self
+    }
+
+    /// Gives up on a token exchange that takes longer than ```timeout```,
+    /// with ```AuthError::NetworkErr```. The default is 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The project the service account belongs to.
+    pub fn project_id(&self) -> Option<&str> {
+        self.project_id.as_ref().map(|s| s as &str)
+    }
+
+    /// The email address identifying the service account.
+    pub fn client_email(&self) -> &str {
+        &self.client_email
+    }
+
+    /// Where the access token is put on every request.
+    pub fn location(&self) -> TokenLocation {
+        self.location
+    }
+
+    /// Returns a valid access token, only asking the token endpoint
+    /// for a new one if the cached token is about to expire.
+    pub fn access_token(&self) -> Result<String, AuthError> {
+        let token = try!( self.token() );
+        Ok(token.value().to_string())
+    }
+
+    /// Always asks the token endpoint for a new access token.
+    pub fn fetch_token(&self) -> Result<ExpiringToken, AuthError> {
+        let now = unix_now();
+        let mut claims = BTreeMap::new();
+        claims.insert("iss".to_string(),   Json::String(self.client_email.clone()));
+        claims.insert("scope".to_string(), Json::String(DATABASE_SCOPES.to_string()));
+        claims.insert("aud".to_string(),   Json::String(self.token_uri.clone()));
+        claims.insert("iat".to_string(),   Json::U64(now));
+        claims.insert("exp".to_string(),   Json::U64(now + TOKEN_LIFETIME));
+        let assertion = try!( self.sign_jwt(claims) );
+
+        let body = form_urlencoded::serialize(vec![
+            ("grant_type", JWT_BEARER_GRANT),
+            ("assertion",  &assertion as &str),
+        ]);
+        let url = try!( Url::parse(&self.token_uri).map_err(AuthError::InvalidTokenUri) );
+        let default_client;
+        let client = match self.client {
+            Some(ref client) => client,
+            None             => { default_client = Arc::new(Client::new()); &default_client },
+        };
+        let timeout = timeout_millis(self.timeout) as usize;
+
+        let mut conn = client::checkout(client, &url, &Method::POST);
+        conn.handle = conn.handle.connect_timeout(timeout).timeout(timeout);
+        let res = conn.handle
+            .post(&self.token_uri as &str, &body as &str)
+            .content_type("application/x-www-form-urlencoded")
+            .exec();
+        client::checkin(client, conn);
+        let res = try!( res.map_err(AuthError::NetworkErr) );
+
+        let body = try!( str::from_utf8(res.get_body()).map_err(AuthError::RespNotUTF8) );
+        if res.get_code() != 200 {
+            return Err(AuthError::TokenRejected(Response {
+                body:  body.to_string(),
+                code:  res.get_code(),
+                stale: false,
+            }));
+        }
+
+        let resp = try!( Json::from_str(body).map_err(|_| AuthError::InvalidTokenResp) );
+        let token = try!( resp.find("access_token")
+                              .and_then(|t| t.as_string())
+                              .ok_or(AuthError::InvalidTokenResp) );
+        let expires_in = resp.find("expires_in")
+                             .and_then(|e| e.as_u64())
+                             .unwrap_or(TOKEN_LIFETIME);
+
+        let token = match self.location {
+            TokenLocation::QueryParam   => AuthToken::AccessToken(token.to_string()),
+            TokenLocation::BearerHeader => AuthToken::Bearer(token.to_string()),
+        };
+        Ok(ExpiringToken {
+            token: token,
+            expires_at: SystemTime::now() + Duration::from_secs(expires_in),
+        })
+    }
+
+    /// Signs ```claims``` into an RS256 JWT with the private key of the account.
+    fn sign_jwt(&self, claims: BTreeMap<String, Json>) -> Result<String, AuthError> {
+        let mut header = BTreeMap::new();
+        header.insert("alg".to_string(), Json::String("RS256".to_string()));
+        header.insert("typ".to_string(), Json::String("JWT".to_string()));
+        if let Some(ref kid) = self.private_key_id {
+            header.insert("kid".to_string(), Json::String(kid.clone()));
+        }
+
+        let message = format!("{}.{}",
+                              Json::Object(header).to_string().as_bytes().to_base64(URL_SAFE),
+                              Json::Object(claims).to_string().as_bytes().to_base64(URL_SAFE));
+
+        let mut signature = vec![0; self.key_pair.public_modulus_len()];
+        try!( self.key_pair.sign(&RSA_PKCS1_SHA256, &SystemRandom::new(),
+                                 message.as_bytes(), &mut signature)
+                           .map_err(|_| AuthError::SigningFailed) );
+
+        Ok(format!("{}.{}", message, signature.to_base64(URL_SAFE)))
+    }
+}
+
+impl TokenProvider for ServiceAccountCredentials {
+    fn token(&self) -> Result<AuthToken, AuthError> {
+        self.cache.get_or_fetch(|| self.fetch_token())
+    }
+}
+
+/// Mints Firebase custom tokens for end users, signed with the private key of a
+/// service account. A client signs in with the token (e.g. ```signInWithCustomToken```
+/// in the JS SDK) and is then seen by security rules as ```auth.uid```, with any
+/// developer claims available under ```auth.token```.
+///
+/// # Examples
+/// ```no_run
+/// # use firebase::*;
+/// # use std::collections::BTreeMap;
+/// let credentials = ServiceAccountCredentials::from_file("service-account.json").unwrap();
+/// let minter = TokenMinter::new(credentials);
+///
+/// let mut claims = BTreeMap::new();
+/// claims.insert("premium".to_string(), Json::Boolean(true));
+/// let token = minter.create_custom_token("some-uid", Some(&Json::Object(claims))).unwrap();
+/// ```
+pub struct TokenMinter {
+    credentials: Arc<ServiceAccountCredentials>,
+    lifetime:    Duration,
+}
+
+impl TokenMinter {
+    /// Creates a minter that signs tokens with ```credentials```, which can be shared
+    /// with a ```Firebase``` instance by passing in an ```Arc```.
+    pub fn new<C>(credentials: C) -> Self
+    where C: Into<Arc<ServiceAccountCredentials>> {
+        TokenMinter {
+            credentials: credentials.into(),
+            lifetime:    Duration::from_secs(TOKEN_LIFETIME),
+        }
+    }
+
+    /// Sets how long minted tokens are valid for, Firebase rejects
+    /// custom tokens that live longer than an hour so that is the most allowed.
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = if lifetime.as_secs() > TOKEN_LIFETIME {
+            Duration::from_secs(TOKEN_LIFETIME)
+        } else {
+            lifetime
+        };
+        self
+    }
+
+    /// Creates a custom token for the user ```uid```, optionally carrying
+    /// developer ```claims```, which must be a JSON object.
+    ///
+    /// # Failures
+    /// - If the uid is empty or longer than 128 characters, ```Err(AuthError::InvalidUid)```.
+    /// - If the claims are not an object, ```Err(AuthError::ClaimsNotObject)```.
+    /// - If a claim is reserved by Firebase (e.g. ```sub```), ```Err(AuthError::ReservedClaim)```.
+    pub fn create_custom_token(&self, uid: &str, claims: Option<&Json>) -> Result<String, AuthError> {
+        if uid.is_empty() || uid.chars().count() > MAX_UID_LENGTH {
+            return Err(AuthError::InvalidUid);
+        }
+
+        let now = unix_now();
+        let email = self.credentials.client_email();
+        let mut payload = BTreeMap::new();
+        payload.insert("iss".to_string(), Json::String(email.to_string()));
+        payload.insert("sub".to_string(), Json::String(email.to_string()));
+        payload.insert("aud".to_string(), Json::String(CUSTOM_TOKEN_AUD.to_string()));
+        payload.insert("iat".to_string(), Json::U64(now));
+        payload.insert("exp".to_string(), Json::U64(now + self.lifetime.as_secs()));
+        payload.insert("uid".to_string(), Json::String(uid.to_string()));
+
+        if let Some(claims) = claims {
+            let claims = try!( claims.as_object().ok_or(AuthError::ClaimsNotObject) );
+            for key in claims.keys() {
+                if RESERVED_CLAIMS.contains(&(key as &str)) {
+                    return Err(AuthError::ReservedClaim(key.clone()));
+                }
+            }
+            if !claims.is_empty() {
+                payload.insert("claims".to_string(), Json::Object(claims.clone()));
+            }
+        }
+
+        self.credentials.sign_jwt(payload)
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Io(io::Error),
+    NotJSON(BuilderError),
+    MissingField(&'static str),
+    InvalidPrivateKey,
+    SigningFailed,
+    NetworkErr(curl::ErrCode),
+    RespNotUTF8(str::Utf8Error),
+    TokenRejected(Response),
+    InvalidTokenResp,
+    InvalidTokenUri(ParseError),
+    InvalidUid,
+    ClaimsNotObject,
+    ReservedClaim(String),
+    TokenTooLong,
+    /// Raised by a ```TokenProvider``` implemented outside of this crate.
+    Other(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthError::Io(ref e)             => write!(f, "could not read credentials: {}", e),
+            AuthError::NotJSON(ref e)        => write!(f, "credentials are not JSON: {}", e),
+            AuthError::MissingField(field)   => write!(f, "credentials are missing `{}`", field),
+            AuthError::InvalidPrivateKey     => f.write_str("invalid private key"),
+            AuthError::SigningFailed         => f.write_str("could not sign token"),
+            AuthError::NetworkErr(ref e)     => write!(f, "token request failed: {}", e),
+            AuthError::RespNotUTF8(ref e)    => write!(f, "token response is not UTF-8: {}", e),
+            AuthError::TokenRejected(ref r)  => write!(f, "token request rejected with status {}", r.code),
+            AuthError::InvalidTokenResp      => f.write_str("invalid token response"),
+            AuthError::InvalidTokenUri(ref e) => write!(f, "invalid token uri: {}", e),
+            AuthError::InvalidUid            => f.write_str("invalid uid"),
+            AuthError::ClaimsNotObject       => f.write_str("claims are not an object"),
+            AuthError::ReservedClaim(ref c)  => write!(f, "claim `{}` is reserved", c),
+            AuthError::TokenTooLong          => f.write_str("token is too long"),
+            AuthError::Other(ref e)          => f.write_str(e),
+        }
+    }
+}
+
+/// Makes ```credentials``` exchange tokens through ```client```,
+/// unless they were given a client of their own.
+pub fn default_client(mut credentials: ServiceAccountCredentials, client: &Arc<Client>) -> ServiceAccountCredentials {
+    if credentials.client.is_none() {
+        credentials.client = Some(client.clone());
+    }
+    credentials
+}
+
+/// Seconds since the unix epoch, as used by the ```iat``` and ```exp``` claims.
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+                     .map(|d| d.as_secs())
+                     .unwrap_or(0)
+}
+
+fn required_field(json: &Json, key: &'static str) -> Result<String, AuthError> {
+    optional_field(json, key).ok_or(AuthError::MissingField(key))
+}
+
+fn optional_field(json: &Json, key: &str) -> Option<String> {
+    json.find(key).and_then(|v| v.as_string()).map(|v| v.to_string())
+}
+
+/// Strips the armor off a PEM encoded key and decodes the base64 body.
+fn pem_to_der(pem: &str) -> Option<Vec<u8>> {
+    let body: String = pem.lines()
+                          .map(|line| line.trim())
+                          .filter(|line| !line.starts_with("-----"))
+                          .collect();
+    body.from_base64().ok()
+}
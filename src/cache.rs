@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+use path::{self, DbPath};
+use Response;
+
+const DEFAULT_CAPACITY: usize = 256;
+
+/// Keeps the results of get requests, so reading the same data again
+/// doesn't have to download it again.
+///
+/// Results are kept per url, so every query has its own entry, whatever the
+/// order its parameters were set in. A result younger
+/// than the time to live is returned without asking the server, an older one is
+/// revalidated with its ETag, and only downloaded again if the data changed.
+/// Writes made through a reference with the cache drop the entries they touch.
+/// When the cache is full, the least recently used entry makes room.
+/// Only the blocking, ```*_async``` and ```*_handle``` requests use the cache,
+/// the futures of the ```nonblocking``` feature always ask the server.
+///
+/// A cache should only be shared by references with the same credentials,
+/// as it doesn't know who is allowed to read what. ```Firebase::auth_with```
+/// leaves it off the reference it creates.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// use std::time::Duration;
+///
+/// let cache = Cache::new(1000).ttl(Duration::from_secs(10));
+/// let config = Firebase::new("https://myfb.firebaseio.com/config").unwrap().with_cache(cache);
+/// ```
+pub struct Cache {
+    capacity: usize,
+    ttl:      Duration,
+    state:    Mutex<State>,
+}
+
+struct State {
+    entries: HashMap<String, Entry>,
+    /// Counts up on every use, to find the least recently used entry.
+    clock:   u64,
+}
+
+struct Entry {
+    path:      DbPath,
+    body:      String,
+    etag:      Option<String>,
+    validated: Instant,
+    used:      u64,
+}
+
+/// What the cache knows about a url.
+pub enum Lookup {
+    /// A result that is young enough to be used as it is.
+    Fresh(Response),
+    /// A result that has to be revalidated with its ETag, if any.
+    Stale(Option<String>),
+}
+
+impl Cache {
+    /// Creates a cache of up to ```capacity``` results that revalidates
+    /// them on every read.
+    pub fn new(capacity: usize) -> Self {
+        Cache {
+            capacity: capacity,
+            ttl:      Duration::from_secs(0),
+            state:    Mutex::new(State {
+                entries: HashMap::new(),
+                clock:   0,
+            }),
+        }
+    }
+
+    /// Sets how long a result is used without asking the server if it changed.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Drops every result.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+
+    /// The number of results in the cache.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cache")
+         .field("capacity", &self.capacity)
+         .field("ttl", &self.ttl)
+         .field("len", &self.len())
+         .finish()
+    }
+}
+
+/// Looks up the result for ```url```.
+pub fn lookup(cache: &Cache, url: &Url) -> Lookup {
+    let mut state = cache.state.lock().unwrap();
+    state.clock += 1;
+    let clock = state.clock;
+    match state.entries.get_mut(&path::canonical(url)) {
+        Some(entry) => {
+            entry.used = clock;
+            if entry.validated.elapsed() < cache.ttl {
+                Lookup::Fresh(Response { body: entry.body.clone(), code: 200, stale: false })
+            } else {
+                Lookup::Stale(entry.etag.clone())
+            }
+        },
+        None => Lookup::Stale(None),
+    }
+}
+
+/// Marks the result for ```url``` as still valid after the server
+/// answered ```304 Not Modified```, and returns it.
+pub fn revalidated(cache: &Cache, url: &Url) -> Option<Response> {
+    let mut state = cache.state.lock().unwrap();
+    state.entries.get_mut(&path::canonical(url)).map(|entry| {
+        entry.validated = Instant::now();
+        Response { body: entry.body.clone(), code: 200, stale: false }
+    })
+}
+
+/// Keeps ```res``` as the result for ```url```, making room if needed.
+pub fn store(cache: &Cache, url: &Url, res: &Response, etag: Option<String>) {
+    if cache.capacity == 0 {
+        return;
+    }
+    let key = path::canonical(url);
+    let mut state = cache.state.lock().unwrap();
+    if !state.entries.contains_key(&key) && state.entries.len() >= cache.capacity {
+        let oldest = state.entries.iter().min_by_key(|&(_, e)| e.used).map(|(k, _)| k.clone());
+        if let Some(oldest) = oldest {
+            state.entries.remove(&oldest);
+        }
+    }
+    state.clock += 1;
+    let entry = Entry {
+        path:      path::from_url(url),
+        body:      res.body.clone(),
+        etag:      etag,
+        validated: Instant::now(),
+        used:      state.clock,
+    };
+    state.entries.insert(key, entry);
+}
+
+/// Drops every result that includes data at, above or below the path of ```url```.
+pub fn invalidate(cache: &Cache, url: &Url) {
+    let written = path::from_url(url);
+    cache.state.lock().unwrap().entries.retain(|_, e| {
+        !(e.path == written || e.path.is_ancestor_of(&written) || written.is_ancestor_of(&e.path))
+    });
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Cancels the requests and listeners of every reference it was given to.
+///
+/// Requests that didn't start yet end with ```Err(ReqErr::Cancelled)```, and
+/// requests that are waiting to be retried aren't tried again. A request
+/// that was already sent can't be aborted, it finishes in the background:
+///
+/// - reads end with ```Err(ReqErr::Cancelled)``` right away, the callbacks of
+///   the ```*_async``` methods are called and the ```*_handle``` methods return
+///   on the thread calling ```cancel```, and futures and listeners of the
+///   ```nonblocking``` feature stop at once,
+/// - writes end with the result of their last attempt, as they may have been
+///   applied by the server, so a write is never reported as cancelled
+///   unless it was never sent.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// let shutdown = CancelToken::new();
+/// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap().cancel_with(&shutdown);
+///
+/// fb.at("/users").unwrap().get_async(|res| {
+///     if let Err(ReqErr::Cancelled) = res {
+///         println!("Shutting down");
+///     }
+/// });
+/// shutdown.cancel();
+/// ```
+#[derive(Clone)]
+pub struct CancelToken {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state:     Mutex<State>,
+    cancelled: Condvar,
+}
+
+struct State {
+    cancelled: bool,
+    next_id:   u64,
+    hooks:     Vec<(u64, Box<FnOnce() + Send>)>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    cancelled: false,
+                    next_id:   0,
+                    hooks:     Vec::new(),
+                }),
+                cancelled: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Cancels every request made with this token, now and from now on.
+    pub fn cancel(&self) {
+        let hooks = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.cancelled = true;
+            state.hooks.drain(..).map(|(_, hook)| hook).collect::<Vec<_>>()
+        };
+        self.shared.cancelled.notify_all();
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.state.lock().unwrap().cancelled
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        CancelToken::new()
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+         .field("cancelled", &self.is_cancelled())
+         .finish()
+    }
+}
+
+/// Calls ```hook``` once ```token``` is cancelled, or right away if it already is.
+/// Returns an id to ```forget``` the hook by, unless it was called.
+pub fn on_cancel<H>(token: &CancelToken, hook: H) -> Option<u64>
+where H: FnOnce() + Send + 'static {
+    let mut state = token.shared.state.lock().unwrap();
+    if state.cancelled {
+        drop(state);
+        hook();
+        return None;
+    }
+    let id = state.next_id;
+    state.next_id += 1;
+    state.hooks.push((id, Box::new(hook)));
+    Some(id)
+}
+
+/// Drops a hook that is no longer needed, e.g. because its request finished.
+pub fn forget(token: &CancelToken, id: u64) {
+    token.shared.state.lock().unwrap().hooks.retain(|&(hook, _)| hook != id);
+}
+
+/// Sleeps for ```duration```, or until ```token``` is cancelled.
+/// Returns whether it was cancelled.
+pub fn sleep(token: &CancelToken, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    let mut state = token.shared.state.lock().unwrap();
+    while !state.cancelled {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        state = token.shared.cancelled.wait_timeout(state, deadline - now).unwrap().0;
+    }
+    true
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "nonblocking")]
+use std::fs::File;
+#[cfg(feature = "nonblocking")]
+use std::io::Read;
+
+use curl::http;
+use url::Url;
+#[cfg(feature = "nonblocking")]
+use hyper;
+#[cfg(feature = "nonblocking")]
+use hyper_rustls::{ConfigBuilderExt, HttpsConnector, HttpsConnectorBuilder};
+#[cfg(feature = "nonblocking")]
+use rustc_serialize::base64::FromBase64;
+#[cfg(feature = "nonblocking")]
+use rustls;
+
+use {Method, ReqErr};
+use path;
+use executor::{Executor, Task, ThreadPool};
+
+const DEFAULT_MAX_IDLE: usize = 8;
+/// Firebase sends at most 256 MB in a single response.
+const DEFAULT_MAX_DECODED: usize = 256 * 1024 * 1024;
+
+/// Keeps the connections of finished requests open so the next requests
+/// to the same host skip the TCP and TLS handshakes, and runs the requests
+/// of the ```*_async``` methods on its ```Executor```.
+///
+/// Every ```Firebase``` reference has a client, which it shares with every
+/// reference created from it. A client can also be shared between databases,
+/// or between references that were created separately, with ```Firebase::with_client```.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// use std::sync::Arc;
+///
+/// let client = Arc::new(Client::new().max_idle_per_route(32));
+/// let users = Firebase::new("https://users.firebaseio.com").unwrap().with_client(client.clone());
+/// let shows = Firebase::new("https://shows.firebaseio.com").unwrap().with_client(client);
+/// ```
+///
+/// A client also decides how connections are made, e.g. through a proxy:
+///
+/// ```
+/// # use firebase::*;
+/// let client = Client::new().proxy("http://proxy.corp:3128")
+///                           .proxy_auth("fry", "slurm")
+///                           .no_proxy(vec!["localhost", ".corp"])
+///                           .ca_bundle("/etc/ssl/corp-ca.pem");
+/// ```
+///
+/// The proxy and CA bundle are also used to fetch the tokens of
+/// ```ServiceAccountCredentials``` that share the client.
+///
+/// # Limits
+/// A client certificate (mutual TLS) and a minimum TLS version are only used by
+/// the futures of the ```nonblocking``` feature. The curl bindings don't expose
+/// them, so blocking and ```*_async``` requests through a client with either
+/// fail with ```ReqErr::Unsupported``` before anything is sent. Tokens are
+/// fetched without them. The ```nonblocking``` futures can't go through a proxy.
+pub struct Client {
+    max_idle:   usize,
+    idle:       Mutex<HashMap<String, Vec<Connection>>>,
+    executor:   Arc<Executor>,
+    proxy:      Option<String>,
+    proxy_auth: Option<(String, String)>,
+    no_proxy:   Vec<String>,
+    ca_bundle:  Option<PathBuf>,
+    max_body:   usize,
+    cert:       Option<(PathBuf, PathBuf)>,
+    min_tls:    Option<TlsVersion>,
+    #[cfg(feature = "nonblocking")]
+    transport:  Mutex<Option<Transport>>,
+}
+
+impl Client {
+    /// Creates a client that keeps up to 8 idle connections per host and
+    /// HTTP method, and runs asynchronous requests on a ```ThreadPool``` of 4 threads.
+    pub fn new() -> Self {
+        Client {
+            max_idle:   DEFAULT_MAX_IDLE,
+            idle:       Mutex::new(HashMap::new()),
+            executor:   Arc::new(ThreadPool::default()),
+            proxy:      None,
+            proxy_auth: None,
+            no_proxy:   Vec::new(),
+            ca_bundle:  None,
+            max_body:   DEFAULT_MAX_DECODED,
+            cert:       None,
+            min_tls:    None,
+            #[cfg(feature = "nonblocking")]
+            transport:  Mutex::new(None),
+        }
+    }
+
+    /// Sets how many idle connections are kept open per host and HTTP method,
+    /// connections finishing a request when there are already that many are
+    /// closed. Zero turns pooling off.
+    ///
+    /// Blocking requests pool their connections per method, as curl keeps a
+    /// custom method like ```PATCH``` on the connection, so a host can have up
+    /// to five times ```max``` idle connections. The futures of the
+    /// ```nonblocking``` feature pool theirs per host only.
+    pub fn max_idle_per_route(mut self, max: usize) -> Self {
+        self.max_idle = max;
+        self
+    }
+
+    /// Sets the executor asynchronous requests are run on, which can be
+    /// shared with the rest of an application by passing in an ```Arc```.
+    pub fn executor<E>(mut self, executor: E) -> Self
+    where E: Executor + 'static {
+        self.executor = Arc::new(executor);
+        self
+    }
+
+    /// Makes every request through the proxy at ```url```, e.g.
+    /// ```http://proxy.corp:3128```. HTTPS requests are tunnelled through it.
+    /// Without a proxy the ```http_proxy``` and ```https_proxy``` environment
+    /// variables are used, like curl does.
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.proxy = Some(url.to_string());
+        self
+    }
+
+    /// Sets the user and password to authenticate with the proxy.
+    pub fn proxy_auth(mut self, user: &str, password: &str) -> Self {
+        self.proxy_auth = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    /// Sets the hosts that are connected to directly instead of through the
+    /// proxy, also one from the environment. A host also matches its subdomains,
+    /// and ```*``` matches every host.
+    pub fn no_proxy<I, S>(mut self, hosts: I) -> Self
+    where I: IntoIterator<Item=S>, S: Into<String> {
+        self.no_proxy = hosts.into_iter().map(|h| h.into().trim_left_matches('.').to_lowercase()).collect();
+        self
+    }
+
+    /// Verifies servers with the certificate authorities in the PEM file
+    /// at ```path```, instead of the ones of the system.
+    pub fn ca_bundle<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ca_bundle = Some(path.into());
+        self
+    }
+
+    /// Sets how large a compressed response may get once it is decompressed,
+    /// larger ones fail with ```ReqErr::RespTooLarge```. Defaults to 256 MiB,
+    /// the most Firebase sends in a single response.
+    pub fn max_decoded_size(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Authenticates with the certificate chain in the PEM file at ```cert```
+    /// and its private key, PKCS#8 or RSA, in the PEM file at ```key```, for
+    /// servers that ask for one. Only supported by the ```nonblocking``` futures.
+    pub fn client_cert<P: Into<PathBuf>>(mut self, cert: P, key: P) -> Self {
+        self.cert = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Refuses to connect with a TLS version older than ```version```.
+    /// Only supported by the ```nonblocking``` futures.
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.min_tls = Some(version);
+        self
+    }
+
+    /// The proxy to use for a request to ```url```, if any.
+    fn proxy_for(&self, url: &Url) -> Option<String> {
+        let host = url.serialize_host().unwrap_or(String::new()).to_lowercase();
+        let bypass = self.no_proxy.iter().any(|h| {
+            h == "*" || host == *h || host.ends_with(&format!(".{}", h))
+        });
+        if bypass {
+            // An empty proxy turns off the proxy curl would take from the environment.
+            return Some(String::new());
+        }
+        let proxy = match self.proxy {
+            Some(ref proxy) => proxy,
+            None            => return None,
+        };
+
+        Some(match self.proxy_auth {
+            Some((ref user, ref password)) => {
+                // Encoded like a path segment, so they can't break up the proxy url.
+                let userinfo = format!("{}:{}@", path::encode_segment(user), path::encode_segment(password));
+                match proxy.find("://") {
+                    Some(i) => format!("{}{}{}", &proxy[..i + 3], userinfo, &proxy[i + 3..]),
+                    None    => userinfo + proxy,
+                }
+            },
+            None => proxy.clone(),
+        })
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The proxy may carry credentials, so it is left out.
+        f.debug_struct("Client")
+         .field("max_idle", &self.max_idle)
+         .field("no_proxy", &self.no_proxy)
+         .field("ca_bundle", &self.ca_bundle)
+         .field("max_body", &self.max_body)
+         .field("cert", &self.cert.as_ref().map(|&(ref cert, _)| cert))
+         .field("min_tls", &self.min_tls)
+         .finish()
+    }
+}
+
+/// A curl handle, which holds on to its connection after a request.
+pub struct Connection {
+    pub handle: http::Handle,
+    key: String,
+}
+
+// A curl handle can move between threads as long as only one uses it at a
+// time, which the pool makes sure of.
+unsafe impl Send for Connection {}
+
+/// A version of TLS, see ```Client::min_tls_version```.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// Fails if ```client``` has options that requests made with curl can't honor.
+pub fn check_curl(client: &Client) -> Result<(), ReqErr> {
+    if client.cert.is_some() {
+        return Err(ReqErr::Unsupported("client certificates are only supported by nonblocking requests"));
+    }
+    if client.min_tls.is_some() {
+        return Err(ReqErr::Unsupported("a minimum TLS version is only supported by nonblocking requests"));
+    }
+    Ok(())
+}
+
+/// How large a response may get once it is decompressed.
+pub fn max_decoded(client: &Client) -> usize {
+    client.max_body
+}
+
+/// Takes an idle connection to the host of ```url``` out of the pool,
+/// or opens a new one if there is none.
+pub fn checkout(client: &Client, url: &Url, method: &Method) -> Connection {
+    let key = pool_key(url, method);
+    let idle = client.idle.lock().unwrap().get_mut(&key).and_then(|conns| conns.pop());
+    match idle {
+        Some(conn) => conn,
+        None       => Connection { handle: configure(client, http::handle(), url), key: key },
+    }
+}
+
+/// Sets the connection options of ```client``` on a new handle. They stay
+/// the same for every request, as handles are only reused for one host.
+fn configure(client: &Client, mut handle: http::Handle, url: &Url) -> http::Handle {
+    if let Some(proxy) = client.proxy_for(url) {
+        handle = handle.proxy(proxy);
+    }
+    if let Some(ref path) = client.ca_bundle {
+        handle = handle.ssl_ca_info(path);
+    }
+    handle
+}
+
+/// Hands a task to the executor of ```client```.
+pub fn execute(client: &Client, task: Task) {
+    client.executor.execute(task);
+}
+
+/// The hyper client the ```nonblocking``` requests of a ```Client``` share.
+#[cfg(feature = "nonblocking")]
+pub type Transport = hyper::Client<HttpsConnector<hyper::client::HttpConnector>>;
+
+/// Returns the transport of ```client``` for a request to ```url```. It is
+/// created on first use and pools the same number of idle connections as
+/// curl does. Proxies are only supported by the blocking requests.
+#[cfg(feature = "nonblocking")]
+pub fn transport(client: &Client, url: &Url) -> Result<Transport, ReqErr> {
+    try!(check_proxy(client, url));
+
+    let mut transport = client.transport.lock().unwrap();
+    if let Some(ref transport) = *transport {
+        return Ok(transport.clone());
+    }
+    let created = try!(new_transport(client, client.max_idle));
+    *transport = Some(created.clone());
+    Ok(created)
+}
+
+/// Creates a transport that keeps no connections, for a request that runs
+/// on a runtime of its own, as connections can't move between runtimes.
+#[cfg(feature = "nonblocking")]
+pub fn unpooled_transport(client: &Client, url: &Url) -> Result<Transport, ReqErr> {
+    try!(check_proxy(client, url));
+    new_transport(client, 0)
+}
+
+#[cfg(feature = "nonblocking")]
+fn check_proxy(client: &Client, url: &Url) -> Result<(), ReqErr> {
+    match client.proxy_for(url) {
+        Some(ref proxy) if !proxy.is_empty() => {
+            Err(ReqErr::Unsupported("proxies are not supported by nonblocking requests"))
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+fn new_transport(client: &Client, max_idle: usize) -> Result<Transport, ReqErr> {
+    let https = HttpsConnectorBuilder::new().with_tls_config(try!(tls_config(client)))
+                                            .https_or_http()
+                                            .enable_http1()
+                                            .build();
+    Ok(hyper::Client::builder().pool_max_idle_per_host(max_idle).build(https))
+}
+
+/// Builds the TLS options of ```client```: the roots it trusts,
+/// the versions it accepts and its certificate, if any.
+#[cfg(feature = "nonblocking")]
+fn tls_config(client: &Client) -> Result<rustls::ClientConfig, ReqErr> {
+    let versions: &[&rustls::SupportedProtocolVersion] = match client.min_tls {
+        Some(TlsVersion::Tls13) => &[&rustls::version::TLS13],
+        _                       => &[&rustls::version::TLS13, &rustls::version::TLS12],
+    };
+    let builder = try!( rustls::ClientConfig::builder().with_safe_default_cipher_suites()
+                                                        .with_safe_default_kx_groups()
+                                                        .with_protocol_versions(versions)
+                                                        .map_err(|e| ReqErr::TransportErr(e.to_string())) );
+    let builder = match client.ca_bundle {
+        Some(ref path) => builder.with_root_certificates(try!(read_roots(path))),
+        None           => builder.with_webpki_roots(),
+    };
+    match client.cert {
+        Some((ref cert, ref key)) => {
+            let bad_cert = |e: &fmt::Display| {
+                ReqErr::TransportErr(format!("invalid client certificate: {}", e))
+            };
+            let chain = try!( read_pem(cert, "CERTIFICATE").map_err(|e| bad_cert(&e)) );
+            let key = try!( read_pem(key, "PRIVATE KEY").map_err(|e| bad_cert(&e)) );
+            let key = try!( key.into_iter().next().ok_or_else(|| bad_cert(&"no private key")) );
+            let chain = chain.into_iter().map(rustls::Certificate).collect();
+            builder.with_single_cert(chain, rustls::PrivateKey(key)).map_err(|e| bad_cert(&e))
+        },
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Trusts the certificates in the PEM file at ```path```.
+#[cfg(feature = "nonblocking")]
+fn read_roots(path: &PathBuf) -> Result<rustls::RootCertStore, ReqErr> {
+    let bad_bundle = |e: &fmt::Display| ReqErr::TransportErr(format!("invalid CA bundle: {}", e));
+    let mut roots = rustls::RootCertStore::empty();
+    for der in try!( read_pem(path, "CERTIFICATE").map_err(|e| bad_bundle(&e)) ) {
+        try!( roots.add(&rustls::Certificate(der)).map_err(|e| bad_bundle(&e)) );
+    }
+    Ok(roots)
+}
+
+/// Reads the blocks of the PEM file at ```path``` whose label ends with
+/// ```label```, e.g. ```PRIVATE KEY``` also matches ```RSA PRIVATE KEY```.
+#[cfg(feature = "nonblocking")]
+fn read_pem(path: &PathBuf, label: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut pem = String::new();
+    try!( File::open(path).and_then(|mut f| f.read_to_string(&mut pem)).map_err(|e| e.to_string()) );
+
+    let mut blocks = Vec::new();
+    for block in pem.split("-----BEGIN ").skip(1) {
+        let (name, rest) = match block.find("-----") {
+            Some(i) => (&block[..i], &block[i + 5..]),
+            None    => return Err("unterminated PEM block".to_string()),
+        };
+        if !name.ends_with(label) {
+            continue;
+        }
+        let base64 = rest.split("-----END ").next().unwrap_or("");
+        blocks.push(try!( base64.from_base64().map_err(|e| e.to_string()) ));
+    }
+    Ok(blocks)
+}
+
+/// Puts a connection back into the pool once its request is done.
+pub fn checkin(client: &Client, conn: Connection) {
+    let mut idle = client.idle.lock().unwrap();
+    let conns = idle.entry(conn.key.clone()).or_insert(Vec::new());
+    if conns.len() < client.max_idle {
+        conns.push(conn);
+    }
+}
+
+/// Connections are pooled per method as well as per host, as curl keeps
+/// a custom method like PATCH on the handle for the requests after it.
+fn pool_key(url: &Url, method: &Method) -> String {
+    let method = match *method {
+        Method::GET    => "GET",
+        Method::POST   => "POST",
+        Method::PUT    => "PUT",
+        Method::PATCH  => "PATCH",
+        Method::DELETE => "DELETE",
+    };
+    format!("{} {}://{}:{}", method, url.scheme,
+            url.serialize_host().unwrap_or(String::new()),
+            url.port_or_default().unwrap_or(0))
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::mem;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::ptr;
+
+use libc;
+use libz_sys as zlib;
+
+use ReqErr;
+
+/// The ```Accept-Encoding``` header sent with every request.
+pub const ACCEPT_ENCODING: &'static str = "gzip, deflate";
+
+/// Output is grown in steps of this many bytes.
+const CHUNK: usize = 64 * 1024;
+/// Lets zlib detect both gzip and zlib headers.
+const AUTO_HEADER: c_int = 15 + 32;
+/// A deflate stream without any header, which some servers send.
+const RAW_DEFLATE: c_int = -15;
+
+/// Why a body couldn't be decompressed.
+enum Failure {
+    Corrupt,
+    TooLarge,
+}
+
+/// Decompresses a body that was sent with ```Content-Encoding: encoding```,
+/// to at most ```limit``` bytes. Fails if the encoding is not known or the body
+/// is corrupt, or if it would decompress to more than that.
+pub fn decode<'b>(encoding: Option<&str>, body: &'b [u8], limit: usize) -> Result<Cow<'b, [u8]>, ReqErr> {
+    let name = encoding.map(|e| e.trim().to_lowercase()).unwrap_or(String::new());
+    let decoded = match &name[..] {
+        "" | "identity"      => return Ok(Cow::Borrowed(body)),
+        "gzip" | "x-gzip"    => inflate(body, AUTO_HEADER, limit),
+        "deflate"            => match inflate(body, AUTO_HEADER, limit) {
+            Err(Failure::Corrupt) => inflate(body, RAW_DEFLATE, limit),
+            other                 => other,
+        },
+        _ => Err(Failure::Corrupt),
+    };
+    match decoded {
+        Ok(decoded)            => Ok(Cow::Owned(decoded)),
+        Err(Failure::TooLarge) => Err(ReqErr::RespTooLarge(limit)),
+        Err(Failure::Corrupt)  => Err(ReqErr::RespNotDecodable(encoding.unwrap_or("").to_string())),
+    }
+}
+
+fn inflate(body: &[u8], window_bits: c_int, limit: usize) -> Result<Vec<u8>, Failure> {
+    unsafe {
+        let mut stream = zlib::z_stream {
+            next_in:   ptr::null_mut(),
+            avail_in:  0,
+            total_in:  0,
+            next_out:  ptr::null_mut(),
+            avail_out: 0,
+            total_out: 0,
+            msg:       ptr::null_mut(),
+            state:     ptr::null_mut(),
+            zalloc:    zalloc,
+            zfree:     zfree,
+            opaque:    ptr::null_mut(),
+            data_type: 0,
+            adler:     0,
+            reserved:  0,
+        };
+        // zlib keeps a pointer to the stream, so it must not move from here on.
+        let init = zlib::inflateInit2_(&mut stream, window_bits, zlib::zlibVersion(),
+                                       mem::size_of::<zlib::z_stream>() as c_int);
+        if init != zlib::Z_OK {
+            return Err(Failure::Corrupt);
+        }
+
+        let mut input = body;
+        let mut out: Vec<u8> = Vec::with_capacity(cmp::min(cmp::max(body.len() * 4, CHUNK), limit));
+        let done = loop {
+            if stream.avail_in == 0 && !input.is_empty() {
+                let len = cmp::min(input.len(), c_uint::max_value() as usize);
+                stream.next_in = input.as_ptr() as *mut _;
+                stream.avail_in = len as c_uint;
+                input = &input[len..];
+            }
+
+            out.reserve(CHUNK);
+            let len = out.len();
+            // One byte more than the limit is enough to tell the body is too large.
+            let room = cmp::min(out.capacity() - len, (limit - len).saturating_add(1));
+            let room = cmp::min(room, c_uint::max_value() as usize) as c_uint;
+            stream.next_out = out.as_mut_ptr().offset(len as isize);
+            stream.avail_out = room;
+
+            let ret = zlib::inflate(&mut stream, zlib::Z_NO_FLUSH);
+            out.set_len(len + (room - stream.avail_out) as usize);
+            match ret {
+                _ if out.len() > limit => break Err(Failure::TooLarge),
+                zlib::Z_STREAM_END     => break Ok(()),
+                zlib::Z_OK             => continue,
+                // Anything else, including running out of input, is an error.
+                _                      => break Err(Failure::Corrupt),
+            }
+        };
+        zlib::inflateEnd(&mut stream);
+
+        done.map(|_| out)
+    }
+}
+
+unsafe extern "C" fn zalloc(_: *mut c_void, items: c_uint, size: c_uint) -> *mut c_void {
+    libc::calloc(items as libc::size_t, size as libc::size_t)
+}
+
+unsafe extern "C" fn zfree(_: *mut c_void, address: *mut c_void) {
+    libc::free(address)
+}
@@ -0,0 +1,343 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use {ReqErr, Response};
+use cancel::{self, CancelToken};
+
+const DEFAULT_THREADS:    usize = 4;
+const DEFAULT_QUEUE_SIZE: usize = 256;
+
+thread_local! {
+    /// The ```Shared``` state of the pool the current thread works for, if any.
+    static POOL: Cell<usize> = Cell::new(0);
+}
+
+/// Runs the requests made by the ```*_async``` methods.
+///
+/// An executor gets a ```Task``` for every request and should call
+/// ```Task::run``` on it, on whatever thread it likes. A task that is dropped
+/// without being run, e.g. because the executor is overloaded, calls its
+/// callback with ```Err(ReqErr::Rejected)``` instead.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// use std::thread;
+///
+/// // Runs every request on a thread of its own.
+/// struct ThreadPerTask;
+///
+/// impl Executor for ThreadPerTask {
+///     fn execute(&self, task: Task) {
+///         thread::spawn(move || task.run());
+///     }
+/// }
+///
+/// let client = Client::new().executor(ThreadPerTask);
+/// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap().with_client(client);
+/// ```
+pub trait Executor: Send + Sync {
+    fn execute(&self, task: Task);
+}
+
+/// A request waiting to be run by an ```Executor```.
+pub struct Task {
+    work: Option<Box<FnOnce(bool) + Send>>,
+}
+
+impl Task {
+    /// Wraps ```work```, which is called with ```true``` when the task is run
+    /// and with ```false``` when it is dropped without running.
+    fn new<F>(work: F) -> Self
+    where F: FnOnce(bool) + Send + 'static {
+        Task {
+            work: Some(Box::new(work)),
+        }
+    }
+
+    /// Makes the request and calls its callback.
+    pub fn run(mut self) {
+        if let Some(work) = self.work.take() {
+            work(true);
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(work) = self.work.take() {
+            work(false);
+        }
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Task")
+    }
+}
+
+/// What a ```ThreadPool``` does with a task when its queue is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backpressure {
+    /// Waits until there is room in the queue. A task made on one of the
+    /// pool's own threads, e.g. by a callback, runs on that thread instead,
+    /// as waiting for the pool from inside it could block it for good.
+    Block,
+    /// Drops the task, so its callback gets ```Err(ReqErr::Rejected)```.
+    Reject,
+    /// Runs the task on the thread that made the request.
+    CallerRuns,
+}
+
+/// A fixed number of threads working off a bounded queue of tasks.
+/// Threads are only started once there is work for them.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// let pool = ThreadPool::new(8).queue_size(1000).backpressure(Backpressure::Reject);
+/// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap()
+///                   .with_client(Client::new().executor(pool));
+/// ```
+pub struct ThreadPool {
+    threads:      usize,
+    queue_size:   usize,
+    backpressure: Backpressure,
+    shared:       Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a task is queued or the pool shuts down.
+    work:  Condvar,
+    /// Signalled when a task leaves the queue.
+    room:  Condvar,
+}
+
+struct State {
+    queue:    VecDeque<Task>,
+    workers:  usize,
+    idle:     usize,
+    shutdown: bool,
+}
+
+impl ThreadPool {
+    /// Creates a pool of ```threads``` threads with room for 256 waiting tasks,
+    /// which blocks the caller when the queue is full.
+    pub fn new(threads: usize) -> Self {
+        ThreadPool {
+            threads:      if threads == 0 { 1 } else { threads },
+            queue_size:   DEFAULT_QUEUE_SIZE,
+            backpressure: Backpressure::Block,
+            shared:       Arc::new(Shared {
+                state: Mutex::new(State {
+                    queue:    VecDeque::new(),
+                    workers:  0,
+                    idle:     0,
+                    shutdown: false,
+                }),
+                work: Condvar::new(),
+                room: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Sets how many tasks can wait for a thread.
+    pub fn queue_size(mut self, size: usize) -> Self {
+        self.queue_size = size;
+        self
+    }
+
+    /// Sets what happens to tasks that don't fit in the queue.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+}
+
+impl Default for ThreadPool {
+    fn default() -> Self {
+        ThreadPool::new(DEFAULT_THREADS)
+    }
+}
+
+impl Executor for ThreadPool {
+    fn execute(&self, task: Task) {
+        let mut state = self.shared.state.lock().unwrap();
+        // Idle threads and threads that are yet to be started
+        // take a task each on top of the queue.
+        while state.queue.len() >= self.queue_size + state.idle + (self.threads - state.workers) {
+            match (self.backpressure, works_for(&self.shared)) {
+                (Backpressure::Block, false) => state = self.shared.room.wait(state).unwrap(),
+                (Backpressure::Reject, _)    => return,
+                // Waiting on one of the pool's threads could wait for itself.
+                (Backpressure::Block, true) | (Backpressure::CallerRuns, _) => {
+                    drop(state);
+                    return task.run();
+                },
+            }
+        }
+
+        state.queue.push_back(task);
+        if state.queue.len() > state.idle && state.workers < self.threads {
+            state.workers += 1;
+            let shared = self.shared.clone();
+            thread::spawn(move || work(shared));
+        }
+        self.shared.work.notify_one();
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Lets the threads finish the queued tasks and stop.
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.work.notify_all();
+    }
+}
+
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThreadPool")
+         .field("threads", &self.threads)
+         .field("queue_size", &self.queue_size)
+         .field("backpressure", &self.backpressure)
+         .finish()
+    }
+}
+
+/// Whether the current thread is one of the threads of the pool of ```shared```.
+fn works_for(shared: &Arc<Shared>) -> bool {
+    POOL.with(|pool| pool.get() == &**shared as *const Shared as usize)
+}
+
+fn work(shared: Arc<Shared>) {
+    POOL.with(|pool| pool.set(&*shared as *const Shared as usize));
+    loop {
+        let task = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if let Some(task) = state.queue.pop_front() {
+                    break task;
+                }
+                if state.shutdown {
+                    state.workers -= 1;
+                    return;
+                }
+                state.idle += 1;
+                state = shared.work.wait(state).unwrap();
+                state.idle -= 1;
+            }
+        };
+        shared.room.notify_one();
+        // A request that panics, e.g. in a token provider, must not take
+        // the thread with it, as the pool would count it as working.
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(move || task.run()));
+    }
+}
+
+/// A handle to a request made by one of the ```*_async``` methods.
+pub struct RequestHandle {
+    done: Receiver<thread::Result<()>>,
+}
+
+impl RequestHandle {
+    /// Waits for the request and its callback to finish. Like joining a thread,
+    /// an error is returned if the callback panicked.
+    pub fn join(self) -> thread::Result<()> {
+        match self.done.recv() {
+            Ok(result) => result,
+            Err(_)     => Err(Box::new("the request was dropped before it finished") as Box<Any + Send>),
+        }
+    }
+}
+
+/// A handle to a request made by one of the ```*_handle``` methods.
+pub struct ResponseHandle {
+    done: Receiver<Result<Response, ReqErr>>,
+}
+
+impl ResponseHandle {
+    /// Waits for the request to finish and returns its result.
+    pub fn join(self) -> Result<Response, ReqErr> {
+        self.done.recv().unwrap_or(Err(ReqErr::Rejected))
+    }
+}
+
+/// Waits for every request in ```handles``` and returns their results in order.
+pub fn join_all<I>(handles: I) -> Vec<Result<Response, ReqErr>>
+where I: IntoIterator<Item=ResponseHandle> {
+    handles.into_iter().map(|handle| handle.join()).collect()
+}
+
+/// Creates a handle and the sender its result is passed to.
+pub fn response_handle() -> (Sender<Result<Response, ReqErr>>, ResponseHandle) {
+    let (tx, rx) = channel();
+    (tx, ResponseHandle { done: rx })
+}
+
+/// Wraps a request and its callback in a task. The callback is called once,
+/// with the result of ```request```, or with ```Err(ReqErr::Rejected)``` if the task
+/// is dropped without running. Once ```cancel``` is cancelled, it is called with
+/// ```Err(ReqErr::Cancelled)``` right away if the request didn't start yet, or if
+/// it may be ```abandon```ed while it runs. A request that is already running
+/// can't be stopped, it goes on in the background. The handle is done once the
+/// callback has returned, a panic in it is passed on to the handle.
+pub fn task<R, C>(request: R, callback: C, cancel: Option<&CancelToken>,
+                  abandon: bool) -> (Task, RequestHandle)
+where R: FnOnce() -> Result<Response, ReqErr> + Send + 'static,
+      C: FnOnce(Result<Response, ReqErr>) + Send + 'static {
+    let (tx, rx) = channel();
+    let slot = Arc::new(Mutex::new(Slot {
+        callback: Some((callback, tx)),
+        started:  false,
+    }));
+
+    let hook = cancel.and_then(|token| {
+        let slot = slot.clone();
+        cancel::on_cancel(token, move || {
+            let callback = {
+                let mut slot = slot.lock().unwrap();
+                if abandon || !slot.started { slot.callback.take() } else { None }
+            };
+            reply(callback, Err(ReqErr::Cancelled));
+        }).map(|id| (token.clone(), id))
+    });
+    let task = Task::new(move |run| {
+        // A task that was cancelled before it started doesn't send its request.
+        let run = run && {
+            let mut slot = slot.lock().unwrap();
+            slot.started = true;
+            slot.callback.is_some()
+        };
+        let result = if run { request() } else { Err(ReqErr::Rejected) };
+        if let Some((token, id)) = hook {
+            cancel::forget(&token, id);
+        }
+        let callback = slot.lock().unwrap().callback.take();
+        reply(callback, result);
+    });
+    (task, RequestHandle { done: rx })
+}
+
+/// The callback of a task until it is called,
+/// and whether the task started its request.
+struct Slot<C> {
+    callback: Option<(C, Sender<thread::Result<()>>)>,
+    started:  bool,
+}
+
+/// Calls the callback, if it wasn't called already.
+fn reply<C>(callback: Option<(C, Sender<thread::Result<()>>)>, result: Result<Response, ReqErr>)
+where C: FnOnce(Result<Response, ReqErr>) {
+    if let Some((callback, done)) = callback {
+        let _ = done.send(panic::catch_unwind(panic::AssertUnwindSafe(move || callback(result))));
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use ring::hmac;
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use rustc_serialize::json::Json;
+
+use auth::{AuthError, unix_now};
+
+const TOKEN_VERSION:    u64 = 0;
+const MAX_UID_LENGTH:   usize = 256;
+/// Firebase refuses legacy tokens longer than this.
+const MAX_TOKEN_LENGTH: usize = 1024;
+
+/// Generates auth tokens in the legacy format signed with a database secret,
+/// the same tokens the ```firebase-token-generator``` libraries create.
+/// The generated token can be passed straight to ```Firebase::authed```.
+///
+/// Database secrets are deprecated, prefer ```TokenMinter``` on projects
+/// that have service accounts.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// # use std::collections::BTreeMap;
+/// let generator = LegacyTokenGenerator::new("<database secret>");
+///
+/// let mut data = BTreeMap::new();
+/// data.insert("uid".to_string(), Json::String("some-uid".to_string()));
+/// let token = generator.create_token(Some(&Json::Object(data)), &LegacyTokenOptions::default()).unwrap();
+///
+/// let fb = Firebase::authed("https://myfb.firebaseio.com", &token).unwrap();
+/// ```
+pub struct LegacyTokenGenerator {
+    key: hmac::Key,
+}
+
+impl LegacyTokenGenerator {
+    /// Creates a generator that signs tokens with the database ```secret```.
+    pub fn new(secret: &str) -> Self {
+        LegacyTokenGenerator {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        }
+    }
+
+    /// Creates a token carrying ```data```, which is available to security rules
+    /// as ```auth```. Unless the token is an admin token the data must be an
+    /// object with a ```uid``` string.
+    ///
+    /// # Failures
+    /// - If the data is not an object, ```Err(AuthError::ClaimsNotObject)```.
+    /// - If the uid is missing, not a string or longer than 256 characters
+    ///   on a non admin token, ```Err(AuthError::InvalidUid)```.
+    /// - If the token ends up longer than 1024 characters, ```Err(AuthError::TokenTooLong)```.
+    pub fn create_token(&self, data: Option<&Json>, options: &LegacyTokenOptions) -> Result<String, AuthError> {
+        let data = match data {
+            Some(d) => Some(try!( d.as_object().ok_or(AuthError::ClaimsNotObject) )),
+            None    => None,
+        };
+
+        if !options.admin {
+            let uid = data.and_then(|d| d.get("uid")).and_then(|uid| uid.as_string());
+            match uid {
+                Some(uid) if uid.chars().count() <= MAX_UID_LENGTH => {},
+                _ => return Err(AuthError::InvalidUid),
+            }
+        }
+
+        let mut claims = BTreeMap::new();
+        claims.insert("v".to_string(),   Json::U64(TOKEN_VERSION));
+        claims.insert("iat".to_string(), Json::U64(options.issued_at.unwrap_or(unix_now())));
+        if let Some(data) = data {
+            claims.insert("d".to_string(), Json::Object(data.clone()));
+        }
+        if let Some(expires) = options.expires {
+            claims.insert("exp".to_string(), Json::U64(expires));
+        }
+        if let Some(not_before) = options.not_before {
+            claims.insert("nbf".to_string(), Json::U64(not_before));
+        }
+        if options.admin {
+            claims.insert("admin".to_string(), Json::Boolean(true));
+        }
+        if options.debug {
+            claims.insert("debug".to_string(), Json::Boolean(true));
+        }
+        if options.simulate {
+            claims.insert("simulate".to_string(), Json::Boolean(true));
+        }
+
+        let mut header = BTreeMap::new();
+        header.insert("alg".to_string(), Json::String("HS256".to_string()));
+        header.insert("typ".to_string(), Json::String("JWT".to_string()));
+
+        let message = format!("{}.{}",
+                              Json::Object(header).to_string().as_bytes().to_base64(URL_SAFE),
+                              Json::Object(claims).to_string().as_bytes().to_base64(URL_SAFE));
+        let signature = hmac::sign(&self.key, message.as_bytes());
+        let token = format!("{}.{}", message, signature.as_ref().to_base64(URL_SAFE));
+
+        if token.len() > MAX_TOKEN_LENGTH {
+            return Err(AuthError::TokenTooLong);
+        }
+        Ok(token)
+    }
+}
+
+/// Options of a legacy token, all options can be omitted by
+/// extending ```LegacyTokenOptions::default()```.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// let options = LegacyTokenOptions {
+///     expires: Some(1893456000),
+///     debug:   true,
+///     .. LegacyTokenOptions::default()
+/// };
+/// ```
+#[derive(Debug)]
+pub struct LegacyTokenOptions {
+    /// Seconds since the epoch after which the token is no longer valid.
+    pub expires:    Option<u64>,
+    /// Seconds since the epoch before which the token is not valid yet.
+    pub not_before: Option<u64>,
+    /// Seconds since the epoch the token was issued at, defaults to now.
+    pub issued_at:  Option<u64>,
+    /// Bypasses all security rules.
+    pub admin:      bool,
+    /// Makes Firebase send back debug output of the security rules.
+    pub debug:      bool,
+    /// Evaluates the security rules without reading or writing data.
+    pub simulate:   bool,
+}
+
+impl Default for LegacyTokenOptions {
+    fn default() -> Self {
+        LegacyTokenOptions {
+            expires:    None,
+            not_before: None,
+            issued_at:  None,
+            admin:      false,
+            debug:      false,
+            simulate:   false,
+        }
+    }
+}
@@ -5,26 +5,73 @@
 
 extern crate curl;
 extern crate url;
+extern crate ring;
 extern crate rustc_serialize;
+extern crate libc;
+extern crate libz_sys;
+#[cfg(feature = "nonblocking")] extern crate hyper;
+#[cfg(feature = "nonblocking")] extern crate hyper_rustls;
+#[cfg(feature = "nonblocking")] extern crate rustls;
+#[cfg(feature = "nonblocking")] extern crate tokio;
+#[cfg(feature = "nonblocking")] extern crate futures_util;
 
 use std::str;
+use std::env;
+use std::fmt;
+use std::io;
+use std::cmp;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::thread;
-use std::thread::JoinHandle;
+use std::time::Duration;
 
 use curl::http;
 use url::Url;
+use url::form_urlencoded;
 
 use rustc_serialize::Decodable;
 use rustc_serialize::json;
-pub use rustc_serialize::json::{Json, BuilderError, DecoderError};
+use cache::Lookup;
+pub use rustc_serialize::json::{Json, BuilderError, DecoderError, ParserError};
+pub use path::DbPath;
+pub use auth::{ServiceAccountCredentials, TokenLocation, TokenMinter, AuthError};
+pub use token::{TokenProvider, AuthToken, ExpiringToken, CachedToken};
+pub use legacy::{LegacyTokenGenerator, LegacyTokenOptions};
+pub use retry::RetryPolicy;
+pub use client::{Client, TlsVersion};
+pub use cancel::CancelToken;
+pub use cache::Cache;
+pub use queue::{WriteQueue, QueuedWrite, Conflict};
+pub use store::SnapshotStore;
+pub use snapshot::DataSnapshot;
+pub use stream::{StreamParser, ParseEvent};
+pub use executor::{Executor, Task, ThreadPool, Backpressure, RequestHandle, ResponseHandle, join_all};
+
+mod path;
+mod auth;
+mod token;
+mod legacy;
+mod retry;
+mod client;
+mod executor;
+mod cancel;
+mod encoding;
+mod stream;
+mod cache;
+mod queue;
+mod store;
+mod snapshot;
+#[cfg(feature = "nonblocking")]
+pub mod nonblocking;
 
 /// A Firebase instance to manage data.
 #[derive(Clone)]
 pub struct Firebase {
     url: Arc<Url>,
+    path: DbPath,
+    auth: Option<Arc<TokenProvider>>,
+    settings: Settings,
 }
 
 // TODO: Change all instances of &str to Into<String>
@@ -42,16 +89,25 @@ impl Firebase {
     ///   will be returned.
     /// - If a url cannot be parsed into a valid url then a ```Err(ParseError::Parser(url::ParseError)```
     ///   will be returned.
+    ///
+    /// # Emulator
+    /// If the ```FIREBASE_DATABASE_EMULATOR_HOST``` environment variable is set,
+    /// e.g. to ```localhost:9000```, the reference points to the same path
+    /// on the emulator instead, see ```Firebase::emulator```. The namespace is
+    /// the ```ns``` parameter of the url, or else the first label of its host.
     pub fn new(url: &str) -> Result<Self, ParseError> {
         let url = try!( parse(&url) );
+        if let Ok(host) = env::var(EMULATOR_HOST_VAR) {
+            let namespace = try!( namespace_of(&url) );
+            let fb = try!( Firebase::emulator(&host, &namespace) );
+            return Ok(fb.at_path(&path::from_url(&url)));
+        }
         if url.scheme != "https" {
             return Err(ParseError::UrlIsNotHTTPS);
         }
         try!( unwrap_path(&url) );
 
-        Ok(Firebase {
-            url: Arc::new(url),
-        })
+        Ok(Firebase::from_parts(url))
     }
 
     /// Creates a firebase reference from a borrow of a Url instance.
@@ -64,9 +120,33 @@ impl Firebase {
         let url = url.clone();
         try!( unwrap_path(&url) );
 
-        Ok(Firebase {
-            url: Arc::new(url),
-        })
+        Ok(Firebase::from_parts(url))
+    }
+
+    /// Creates a Firebase instance for the local Realtime Database emulator
+    /// running at ```host```, e.g. ```localhost:9000```. Plain HTTP is used,
+    /// every request names the database with the ```ns``` parameter and is
+    /// authenticated with the emulator's owner credential, which bypasses
+    /// security rules. Use ```auth_with``` to test rules as someone else.
+    ///
+    /// # Examples
+    /// ```
+    /// # use firebase::Firebase;
+    /// let fb = Firebase::emulator("localhost:9000", "myfb").unwrap();
+    /// assert_eq!(fb.get_url(), "http://localhost:9000/.json?ns=myfb");
+    /// ```
+    ///
+    /// # Failures
+    /// - If the host cannot be parsed into a valid url then a ```Err(ParseError::Parser(url::ParseError)```
+    ///   will be returned.
+    pub fn emulator(host: &str, namespace: &str) -> Result<Self, ParseError> {
+        let host = host.trim_left_matches("http://").trim_right_matches('/');
+        let mut url = try!( parse(&format!("http://{}/", host)) );
+        try!( unwrap_path(&url) );
+        url.set_query_from_pairs(vec![(NAMESPACE, namespace)].into_iter());
+
+        let fb = Firebase::from_parts(url);
+        Ok(fb.auth_with(AuthToken::Bearer(EMULATOR_OWNER.to_string())))
     }
 
     /// Creates a new authenticated Firebase instance from the firebaseio url and an auth token.
@@ -75,7 +155,7 @@ impl Firebase {
     /// ```
     /// # use firebase::Firebase;
     /// let fb = Firebase::authed("https://myfb.firebaseio.com", "deadbeefcafe");
-    /// // The url shoud now be: https://myfb.firebaseio.com?auth=deadbeefcafe
+    /// // Every request is now sent to: https://myfb.firebaseio.com?auth=deadbeefcafe
     /// ```
     ///
     /// # Failures
@@ -84,18 +164,161 @@ impl Firebase {
     /// - If a url cannot be parsed into a valid url then a ```Err(ParseError::Parser(url::ParseError)```
     ///   will be returned.
     pub fn authed(url: &str, auth_token: &str) -> Result<Self, ParseError> {
-        let mut url = try!( parse(&url) );
-        if url.scheme != "https" {
-            return Err(ParseError::UrlIsNotHTTPS);
+        Firebase::with_token_provider(url, AuthToken::Auth(auth_token.to_string()))
+    }
+
+    /// Creates a new Firebase instance authenticated as a Google service account.
+    /// Every request carries an OAuth2 access token that is fetched with the
+    /// credentials and refreshed before it expires, see ```ServiceAccountCredentials```.
+    /// Unless the credentials have a client of their own, tokens are fetched
+    /// through the client of the new reference, which ```with_client``` doesn't change.
+    ///
+    /// # Failures
+    /// - If a url is not specified with the HTTPS scheme, a ```Err(ParseError::UrlIsNotHTTPS)```
+    ///   will be returned.
+    /// - If a url cannot be parsed into a valid url then a ```Err(ParseError::Parser(url::ParseError)```
+    ///   will be returned.
+    pub fn with_service_account(url: &str, credentials: ServiceAccountCredentials) -> Result<Self, ParseError> {
+        let fb = try!( Firebase::new(url) );
+        if env::var_os(EMULATOR_HOST_VAR).is_some() {
+            return Ok(fb);
         }
-        try!( unwrap_path(&url) );
+        let credentials = auth::default_client(credentials, &fb.settings.client);
+        Ok(fb.auth_with(credentials))
+    }
 
-        let opts = vec![ (AUTH, auth_token) ];
-        url.set_query_from_pairs(opts.into_iter());
+    /// Creates a new Firebase instance that asks ```provider``` for a token
+    /// on every request, see ```TokenProvider```. When ```new``` picks the
+    /// emulator up from the environment the provider is ignored and the owner
+    /// credential is used, like the other Firebase SDKs do.
+    ///
+    /// # Failures
+    /// - If a url is not specified with the HTTPS scheme, a ```Err(ParseError::UrlIsNotHTTPS)```
+    ///   will be returned.
+    /// - If a url cannot be parsed into a valid url then a ```Err(ParseError::Parser(url::ParseError)```
+    ///   will be returned.
+    pub fn with_token_provider<P>(url: &str, provider: P) -> Result<Self, ParseError>
+    where P: TokenProvider + 'static {
+        let fb = try!( Firebase::new(url) );
+        if env::var_os(EMULATOR_HOST_VAR).is_some() {
+            return Ok(fb);
+        }
+        Ok(fb.auth_with(provider))
+    }
 
-        Ok(Firebase {
-            url: Arc::new(url),
-        })
+    /// Creates a new reference to the same location that authenticates
+    /// its requests with ```provider``` instead. The cache, snapshot store and
+    /// write queue are left off, as what they hold was read or written with
+    /// the old credentials.
+    pub fn auth_with<P>(&self, provider: P) -> Self
+    where P: TokenProvider + 'static {
+        let mut settings = self.settings.clone();
+        settings.cache = None;
+        settings.store = None;
+        settings.queue = None;
+        Firebase {
+            url: self.url.clone(),
+            path: self.path.clone(),
+            auth: Some(Arc::new(provider)),
+            settings: settings,
+        }
+    }
+
+    /// Creates a new reference to the same location whose requests fail with
+    /// ```ReqErr::Timeout``` if no connection is made within ```timeout```.
+    /// Defaults to 30 seconds.
+    ///
+    /// References are cheap to create, so this can also be used for a single call.
+    ///
+    /// # Examples
+    /// ```
+    /// # use firebase::Firebase;
+    /// use std::time::Duration;
+    ///
+    /// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap()
+    ///                   .connect_timeout(Duration::from_secs(2));
+    /// let users = fb.at("/users").unwrap();
+    /// let first = users.request_timeout(Duration::from_secs(5)).get();
+    /// ```
+    pub fn connect_timeout(&self, timeout: Duration) -> Self {
+        let mut fb = self.clone();
+        fb.settings.connect_timeout = Some(timeout);
+        fb
+    }
+
+    /// Creates a new reference to the same location whose requests fail with
+    /// ```ReqErr::Timeout``` if they take longer than ```timeout``` in total.
+    /// By default requests can take as long as they need, e.g. for large exports,
+    /// but fail once no data came in for 30 seconds, plus the server
+    /// ```timeout``` of the read if one is set.
+    pub fn request_timeout(&self, timeout: Duration) -> Self {
+        let mut fb = self.clone();
+        fb.settings.request_timeout = Some(timeout);
+        fb
+    }
+
+    /// Creates a new reference to the same location that retries failed
+    /// requests as told by ```policy```, see ```RetryPolicy```.
+    pub fn retry_policy(&self, policy: RetryPolicy) -> Self {
+        let mut fb = self.clone();
+        fb.settings.retry = Some(policy);
+        fb
+    }
+
+    /// Creates a new reference to the same location whose requests
+    /// are cancelled by ```token```, see ```CancelToken```.
+    pub fn cancel_with(&self, token: &CancelToken) -> Self {
+        let mut fb = self.clone();
+        fb.settings.cancel = Some(token.clone());
+        fb
+    }
+
+    /// Creates a new reference to the same location that keeps the results
+    /// of its reads in ```cache```, see ```Cache```.
+    pub fn with_cache<C>(&self, cache: C) -> Self
+    where C: Into<Arc<Cache>> {
+        let mut fb = self.clone();
+        fb.settings.cache = Some(cache.into());
+        fb
+    }
+
+    /// Creates a new reference to the same location that saves what it reads,
+    /// to read it while the server can't be reached, see ```SnapshotStore```.
+    pub fn with_snapshot_store<S>(&self, store: S) -> Self
+    where S: Into<Arc<SnapshotStore>> {
+        let mut fb = self.clone();
+        fb.settings.store = Some(store.into());
+        fb
+    }
+
+    /// Creates a new reference to the same location whose writes are
+    /// queued while the server can't be reached, see ```WriteQueue```.
+    pub fn with_write_queue<Q>(&self, queue: Q) -> Self
+    where Q: Into<Arc<WriteQueue>> {
+        let mut fb = self.clone();
+        fb.settings.queue = Some(queue.into());
+        fb
+    }
+
+    /// Replays the writes waiting in the ```WriteQueue``` of this reference,
+    /// authorized like this reference. Returns how many are still waiting.
+    pub fn replay_writes(&self) -> usize {
+        let auth = self.auth.as_ref().map(|a| &**a);
+        match self.settings.queue {
+            Some(ref queue) => queue::replay(queue, |url, method, data| {
+                Firebase::write_url(url, auth, &self.settings, method, data)
+            }),
+            None => 0,
+        }
+    }
+
+    /// Creates a new reference to the same location that makes its requests
+    /// over the connections of ```client```, see ```Client```.
+    pub fn with_client<C>(&self, client: C) -> Self
+    where C: Into<Arc<Client>> {
+        let mut fb = self.clone();
+        fb.settings.client = client.into();
+        fb
     }
 
     /// Creates a new firebase instance that extends the path of an old firebase instance.
@@ -112,29 +335,94 @@ impl Firebase {
     /// // A new reference to /friends/yasha/messages
     /// let messages = yasha.at("messages").unwrap();
     pub fn at(&self, add_path: &str) -> Result<Self, ParseError> {
-        let mut url = (*self.url).clone();
+        Ok(self.at_path(&DbPath::new(add_path)))
+    }
 
-        { // Add path to original path, already checked for path.
-            let mut path = url.path_mut().unwrap();
-            // Remove .json from the old path's end.
-            if let Some(end) = path.pop() {
-                path.push(end.trim_right_matches(".json").to_string());
-            }
-            let add_path = add_path.trim_matches('/');
-            let add_path = if !add_path.ends_with(".json") {
-                Cow::Owned(add_path.to_string() + ".json")
-            } else {
-                Cow::Borrowed(add_path)
-            };
+    /// Same as ```at``` but extends the path of this reference with an
+    /// already parsed ```DbPath```.
+    pub fn at_path(&self, add_path: &DbPath) -> Self {
+        self.with_path(self.path.join(add_path))
+    }
 
-            for component in add_path.split("/").into_iter() {
-                path.push(component.to_string());
-            }
+    /// Creates a new reference to the same location whose requests are evaluated
+    /// by the security rules as if made by a user with the ```auth``` variable
+    /// set to ```auth```, using the ```auth_variable_override``` parameter.
+    /// Pass ```Json::Null``` to act as an unauthenticated user.
+    ///
+    /// This only has an effect on requests made with admin credentials (a database
+    /// secret or a service account), that are downscoped to the given user.
+    /// The override is kept by references and queries created from this one.
+    ///
+    /// # Examples
+    /// ```
+    /// # use firebase::*;
+    /// # use std::collections::BTreeMap;
+    /// let fb = Firebase::authed("https://myfb.firebaseio.com", "<database secret>").unwrap();
+    /// let mut auth = BTreeMap::new();
+    /// auth.insert("uid".to_string(), Json::String("yasha".to_string()));
+    /// let as_yasha = fb.auth_variable_override(&Json::Object(auth));
+    /// // Fails if yasha may not read messages.
+    /// let messages = as_yasha.at("/messages").unwrap().get();
+    /// ```
+    pub fn auth_variable_override(&self, auth: &Json) -> Self {
+        let mut url = (*self.url).clone();
+        let mut pairs: Vec<(String, String)> = url.query_pairs().unwrap_or(Vec::new())
+            .into_iter()
+            .filter(|&(ref k, _)| k != AUTH_OVERRIDE)
+            .collect();
+        pairs.push((AUTH_OVERRIDE.to_string(), auth.to_string()));
+        url.set_query_from_pairs(pairs.into_iter());
+
+        Firebase {
+            url: Arc::new(url),
+            path: self.path.clone(),
+            auth: self.auth.clone(),
+            settings: self.settings.clone(),
         }
+    }
 
-        Ok(Firebase {
-            url: Arc::new(url),
-        })
+    /// Creates a reference to the parent location of this reference.
+    /// The root of the database has no parent, so ```None``` is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// # use firebase::Firebase;
+    /// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap();
+    /// let messages = fb.at("/friends/yasha/messages").unwrap();
+    /// let yasha = messages.parent().unwrap();
+    /// assert_eq!(yasha.path().to_string(), "/friends/yasha");
+    /// assert!(fb.parent().is_none());
+    /// ```
+    pub fn parent(&self) -> Option<Self> {
+        self.path.parent().map(|parent| self.with_path(parent))
+    }
+
+    /// Creates a reference to the root of the database this reference
+    /// belongs to. Query parameters like ```auth``` are kept.
+    pub fn root(&self) -> Self {
+        self.with_path(DbPath::root())
+    }
+
+    /// Returns the key of the location this reference points to, that is the
+    /// last segment of its path with any percent-encoding undone.
+    /// The root of the database has no key, so ```None``` is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// # use firebase::Firebase;
+    /// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap();
+    /// let city = fb.at("/cities/São Paulo").unwrap();
+    /// assert_eq!(city.key(), Some("São Paulo".to_string()));
+    /// assert_eq!(fb.key(), None);
+    /// ```
+    pub fn key(&self) -> Option<String> {
+        self.path.key().map(|key| key.to_string())
+    }
+
+    /// Returns the path of the location this reference points to, relative to
+    /// the root of the database.
+    pub fn path(&self) -> &DbPath {
+        &self.path
     }
 
     /// Creates a FirebaseParams instance, this instance has query parameters
@@ -161,11 +449,12 @@ impl Firebase {
     /// });
     /// ```
     pub fn ops(&self, opts: &FbOps) -> FirebaseParams {
-        FirebaseParams::from_ops(&self.url, opts)
+        FirebaseParams::from_ops(self, opts)
     }
 
     /// Returns the current URL as a string that will be used
     /// to make the REST call when talking to Firebase.
+    /// Tokens are only added when a request is made, so they are not part of it.
     pub fn get_url(&self) -> String {
         self.url.serialize()
     }
@@ -182,6 +471,13 @@ impl Firebase {
         self.request(Method::GET, None)
     }
 
+    /// Gets data from Firebase as a ```DataSnapshot```. Fails with
+    /// ```ReqErr::Refused``` if the response is not successful.
+    pub fn get_snapshot(&self) -> Result<DataSnapshot, ReqErr> {
+        let res = try!( self.get() );
+        snapshot::from_response(&self.url, res, None)
+    }
+
     /// Sets data to Firebase.
     /// # Examples
     /// ```
@@ -231,7 +527,8 @@ impl Firebase {
     }
 
     /// Asynchronous version of the get method, takes a callback
-    /// and returns a handle to the thread making the request to Firebase.
+    /// and returns a handle to the request. Requests are run by the
+    /// ```Executor``` of the reference's ```Client```, a pool of 4 threads by default.
     /// # Examples
     /// ```
     /// # use firebase::Firebase;
@@ -243,37 +540,76 @@ impl Firebase {
     ///         println!("The description changed!");
     ///     }
     /// });
-    pub fn get_async<F>(&self, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static {
-        Firebase::request_url_async(&self.url, Method::GET, None, callback)
+    pub fn get_async<F>(&self, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static {
+        Firebase::request_url_async(&self.url, &self.auth, &self.settings, Method::GET, None, callback)
     }
 
     /// Asynchronous version of the set method, takes a callback
-    /// and returns a handle to the thread making the request to Firebase.
-    pub fn set_async<S, F>(&self, data: S, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static, S: Into<String> {
-        Firebase::request_url_async(&self.url, Method::PUT, Some(data.into()), callback)
+    /// and returns a handle to the request.
+    pub fn set_async<S, F>(&self, data: S, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static, S: Into<String> {
+        Firebase::request_url_async(&self.url, &self.auth, &self.settings, Method::PUT, Some(data.into()), callback)
     }
 
     /// Asynchronous version of the push method, takes a callback
-    /// and returns a handle to the thread making the request to Firebase.
-    pub fn push_async<S, F>(&self, data: S, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static, S: Into<String> {
-        Firebase::request_url_async(&self.url, Method::POST, Some(data.into()), callback)
+    /// and returns a handle to the request.
+    pub fn push_async<S, F>(&self, data: S, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static, S: Into<String> {
+        Firebase::request_url_async(&self.url, &self.auth, &self.settings, Method::POST, Some(data.into()), callback)
     }
 
     /// Asynchronous version of the update method, takes a callback
-    /// and returns a handle to the thread making the request to Firebase.
-    pub fn update_async<S, F>(&self, data: S, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static, S: Into<String> {
-        Firebase::request_url_async(&self.url, Method::PATCH, Some(data.into()), callback)
+    /// and returns a handle to the request.
+    pub fn update_async<S, F>(&self, data: S, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static, S: Into<String> {
+        Firebase::request_url_async(&self.url, &self.auth, &self.settings, Method::PATCH, Some(data.into()), callback)
     }
 
     /// Asynchronous version of the remove method, takes a callback
-    /// and returns a handle to the thread making the request to Firebase.
-    pub fn remove_async<F>(&self, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static {
-        Firebase::request_url_async(&self.url, Method::DELETE, None, callback)
+    /// and returns a handle to the request.
+    pub fn remove_async<F>(&self, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static {
+        Firebase::request_url_async(&self.url, &self.auth, &self.settings, Method::DELETE, None, callback)
+    }
+
+    /// Starts a get request on the client's ```Executor``` and returns
+    /// a handle, whose ```join``` waits for the request and returns its result.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use firebase::*;
+    /// let firebase = Firebase::new("https://shows.firebaseio.com").unwrap();
+    /// let episodes = vec![
+    ///     firebase.at("/futurama/episodes/140").unwrap().get_handle(),
+    ///     firebase.at("/futurama/episodes/141").unwrap().get_handle(),
+    /// ];
+    /// for info in join_all(episodes) {
+    ///     println!("{}", info.unwrap().body);
+    /// }
+    /// ```
+    pub fn get_handle(&self) -> ResponseHandle {
+        Firebase::request_url_handle(&self.url, &self.auth, &self.settings, Method::GET, None)
+    }
+
+    /// Starts a set request and returns a handle to its result, see ```get_handle```.
+    pub fn set_handle<S: Into<String>>(&self, data: S) -> ResponseHandle {
+        Firebase::request_url_handle(&self.url, &self.auth, &self.settings, Method::PUT, Some(data.into()))
+    }
+
+    /// Starts a push request and returns a handle to its result, see ```get_handle```.
+    pub fn push_handle<S: Into<String>>(&self, data: S) -> ResponseHandle {
+        Firebase::request_url_handle(&self.url, &self.auth, &self.settings, Method::POST, Some(data.into()))
+    }
+
+    /// Starts an update request and returns a handle to its result, see ```get_handle```.
+    pub fn update_handle<S: Into<String>>(&self, data: S) -> ResponseHandle {
+        Firebase::request_url_handle(&self.url, &self.auth, &self.settings, Method::PATCH, Some(data.into()))
+    }
+
+    /// Starts a remove request and returns a handle to its result, see ```get_handle```.
+    pub fn remove_handle(&self) -> ResponseHandle {
+        Firebase::request_url_handle(&self.url, &self.auth, &self.settings, Method::DELETE, None)
     }
 
     /// Creates a ```FirebaseParams``` instance, a Firebase struct that only
@@ -331,50 +667,263 @@ impl Firebase {
         self.with_params(FORMAT, EXPORT)
     }
 
+    /// Creates a ```FirebaseParams``` instance, a Firebase struct that only
+    /// knows how to GET data, and asks the server to give up reads that take
+    /// longer than ```limit```. Firebase caps this at 15 minutes.
+    /// This limits the server, ```request_timeout``` limits the client.
+    pub fn timeout(&self, limit: Duration) -> FirebaseParams {
+        self.with_params(TIMEOUT, server_timeout(limit))
+    }
+
     #[inline]
     fn request(&self, method: Method, data: Option<&str>) -> Result<Response, ReqErr> {
-        Firebase::request_url(&self.url, method, data)
+        Firebase::request_url(&self.url, self.auth.as_ref().map(|a| &**a), &self.settings, method, data)
+    }
+
+    fn request_url(url: &Url, auth: Option<&TokenProvider>, settings: &Settings,
+                   method: Method, data: Option<&str>) -> Result<Response, ReqErr> {
+        match (method, settings.queue.as_ref()) {
+            (Method::GET, _) => Firebase::read_url(url, auth, settings),
+            (_, Some(queue)) => queue::write(queue, url, method, data, |url, method, data| {
+                Firebase::write_url(url, auth, settings, method, data)
+            }),
+            (_, None)        => Firebase::write_url(url, auth, settings, method, data),
+        }
     }
 
-    fn request_url(url: &Url, method: Method, data: Option<&str>) -> Result<Response, ReqErr> {
-        let mut handler = http::handle();
+    /// Reads through the cache and the snapshot store, if there are any.
+    fn read_url(url: &Url, auth: Option<&TokenProvider>, settings: &Settings) -> Result<Response, ReqErr> {
+        let result = Firebase::read_cached(url, auth, settings);
+        match settings.store {
+            Some(ref store) => store::read_through(store, url, result),
+            None            => result,
+        }
+    }
 
-        let req = match method {
-            Method::GET     => handler.get(   url),
-            Method::POST    => handler.post(  url, data.unwrap()),
-            Method::PUT     => handler.put(   url, data.unwrap()),
-            Method::PATCH   => handler.patch( url, data.unwrap()),
-            Method::DELETE  => handler.delete(url),
+    fn read_cached(url: &Url, auth: Option<&TokenProvider>, settings: &Settings) -> Result<Response, ReqErr> {
+        let cache = match settings.cache {
+            Some(ref cache) => cache,
+            None            => return Firebase::request_retrying(url, auth, settings, Method::GET, None, None).0,
+        };
+        let etag = match cache::lookup(cache, url) {
+            Lookup::Fresh(res)  => return Ok(res),
+            Lookup::Stale(etag) => etag,
         };
+        match Firebase::request_retrying(url, auth, settings, Method::GET, None, etag.as_ref().map(|e| &e[..])) {
+            (Ok(res), _) if res.code == NOT_MODIFIED => Ok(cache::revalidated(cache, url).unwrap_or(res)),
+            (Ok(res), etag) => {
+                if res.code == OK {
+                    cache::store(cache, url, &res, etag);
+                }
+                Ok(res)
+            },
+            (Err(e), _) => Err(e),
+        }
+    }
 
-        let res = match req.exec() {
+    fn write_url(url: &Url, auth: Option<&TokenProvider>, settings: &Settings,
+                 method: Method, data: Option<&str>) -> Result<Response, ReqErr> {
+        if let Some(ref cache) = settings.cache {
+            // Even a failed write may have reached the server.
+            cache::invalidate(cache, url);
+        }
+        Firebase::request_retrying(url, auth, settings, method, data, None).0
+    }
+
+    /// Makes a request, retrying it as told by the retry policy.
+    /// Also returns the ETag of the response, if it was asked for.
+    ///
+    /// Cancelling stops it before the next attempt. A write that was sent
+    /// ends with the result of its last attempt, as it may have been applied.
+    fn request_retrying(url: &Url, auth: Option<&TokenProvider>, settings: &Settings, method: Method,
+                        data: Option<&str>, if_none_match: Option<&str>) -> (Result<Response, ReqErr>, Option<String>) {
+        let cancelled = || settings.cancel.as_ref().map_or(false, |c| c.is_cancelled());
+        if cancelled() {
+            return (Err(ReqErr::Cancelled), None);
+        }
+        let mut retries = 0;
+        loop {
+            let (result, retry_after, etag) = Firebase::send(url, auth, settings, &method, data, if_none_match);
+            let delay = match settings.retry {
+                Some(ref policy) => retry::next_delay(policy, &method, retries, &result, retry_after),
+                None             => None,
+            };
+            match (delay, settings.cancel.as_ref()) {
+                (Some(delay), Some(cancel)) => { cancel::sleep(cancel, delay); },
+                (Some(delay), None)         => thread::sleep(delay),
+                (None, _)                   => return (result, etag),
+            }
+            if cancelled() {
+                return match method {
+                    Method::GET => (Err(ReqErr::Cancelled), None),
+                    _           => (result, etag),
+                };
+            }
+            retries += 1;
+        }
+    }
+
+    /// Makes a single attempt at a request, also returning the delay asked
+    /// for by a ```Retry-After``` header and the ETag of the response.
+    fn send(url: &Url, auth: Option<&TokenProvider>, settings: &Settings, method: &Method,
+            data: Option<&str>, if_none_match: Option<&str>) -> (Result<Response, ReqErr>, Option<Duration>, Option<String>) {
+        if let Err(e) = client::check_curl(&settings.client) {
+            return (Err(e), None, None);
+        }
+        let (url, bearer) = match authorize(url, auth) {
+            Ok(a)  => a,
+            Err(e) => return (Err(e), None, None),
+        };
+
+        let mut conn = client::checkout(&settings.client, &url, method);
+        conn.handle = settings.apply(conn.handle, &url);
+
+        let res = {
+            let handler = &mut conn.handle;
+            let mut req = match *method {
+                Method::GET     => handler.get(   &*url),
+                Method::POST    => handler.post(  &*url, data.unwrap()),
+                Method::PUT     => handler.put(   &*url, data.unwrap()),
+                Method::PATCH   => handler.patch( &*url, data.unwrap()),
+                Method::DELETE  => handler.delete(&*url),
+            };
+            if let Some(ref bearer) = bearer {
+                req = req.header("Authorization", bearer);
+            }
+            if let (Method::GET, Some(_)) = (*method, settings.cache.as_ref()) {
+                req = req.header("X-Firebase-ETag", "true");
+            }
+            if let Some(etag) = if_none_match {
+                req = req.header("If-None-Match", etag);
+            }
+            req.header("Accept-Encoding", encoding::ACCEPT_ENCODING).exec()
+        };
+        client::checkin(&settings.client, conn);
+
+        let res = match res {
             Ok(r)  => r,
-            Err(e) => return Err(ReqErr::NetworkErr(e)),
+            Err(e) => return (Err(ReqErr::from_curl(e)), None, None),
         };
+        let retry_after = res.get_header("retry-after").first()
+                             .and_then(|value| retry::parse_retry_after(value));
+        let etag = res.get_header("etag").first().map(|e| e.trim().to_string());
 
-        let body = match str::from_utf8(res.get_body()) {
+        let content_encoding = res.get_header("content-encoding").first().map(|e| &e[..]);
+        let limit = client::max_decoded(&settings.client);
+        let body = match encoding::decode(content_encoding, res.get_body(), limit) {
+            Ok(b)  => b,
+            Err(e) => return (Err(e), retry_after, None),
+        };
+        let body = match str::from_utf8(&body) {
             Ok(b)  => b,
-            Err(e) => return Err(ReqErr::RespNotUTF8(e)),
+            Err(e) => return (Err(ReqErr::RespNotUTF8(e)), retry_after, None),
         };
 
-        Ok(Response {
-            body: body.to_string(),
-            code: res.get_code(),
-        })
+        (Ok(Response {
+            body:  body.to_string(),
+            code:  res.get_code(),
+            stale: false,
+        }), retry_after, etag)
     }
 
-    fn request_url_async<F>(url: &Arc<Url>, method: Method, data: Option<String>, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static {
+    fn request_url_async<F>(url: &Arc<Url>, auth: &Option<Arc<TokenProvider>>, settings: &Settings,
+                            method: Method, data: Option<String>, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static {
         // Fast, because its in an arc.
         let url = url.clone();
+        let auth = auth.clone();
+        let client = settings.client.clone();
+        let settings = settings.clone();
+
+        let cancel = settings.cancel.clone();
+
+        // Only a read can be left behind once it started, a write has to tell if it went through.
+        let abandon = match method {
+            Method::GET => true,
+            _           => false,
+        };
+        let (task, handle) = executor::task(move || {
+            Firebase::request_url(&url, auth.as_ref().map(|a| &**a), &settings,
+                                  method, data.as_ref().map(|s| s as &str))
+        }, callback, cancel.as_ref(), abandon);
+        client::execute(&client, task);
+        handle
+    }
+
+    fn request_url_handle(url: &Arc<Url>, auth: &Option<Arc<TokenProvider>>, settings: &Settings,
+                          method: Method, data: Option<String>) -> ResponseHandle {
+        let (tx, handle) = executor::response_handle();
+        Firebase::request_url_async(url, auth, settings, method, data, move |result| {
+            // The handle may have been dropped without being joined.
+            let _ = tx.send(result);
+        });
+        handle
+    }
+
+    fn from_parts(mut url: Url) -> Self {
+        let path = path::from_url(&url);
+        path::set_on_url(&mut url, &path);
+
+        // A token that came with the url is sent with every request,
+        // but kept out of the stored url like any other token.
+        let mut auth = None;
+        if let Some(pairs) = url.query_pairs() {
+            for &(ref k, ref token) in pairs.iter() {
+                if k == AUTH {
+                    auth = Some(Arc::new(AuthToken::Auth(token.clone())) as Arc<TokenProvider>);
+                } else if k == ACCESS_TOKEN {
+                    auth = Some(Arc::new(AuthToken::AccessToken(token.clone())) as Arc<TokenProvider>);
+                }
+            }
+            let rest: Vec<_> = pairs.iter().filter(|&&(ref k, _)| !is_credential(k)).collect();
+            if rest.len() < pairs.len() {
+                if rest.is_empty() {
+                    url.query = None;
+                } else {
+                    url.set_query_from_pairs(rest.into_iter());
+                }
+            }
+        }
+
+        Firebase {
+            url: Arc::new(url),
+            path: path,
+            auth: auth,
+            settings: Settings::default(),
+        }
+    }
+
+    fn with_path(&self, path: DbPath) -> Self {
+        let mut url = (*self.url).clone();
+        path::set_on_url(&mut url, &path);
 
-        thread::spawn(move || {
-            callback(Firebase::request_url(&url, method, data.as_ref().map(|s| s as &str)));
-        })
+        Firebase {
+            url: Arc::new(url),
+            path: path,
+            auth: self.auth.clone(),
+            settings: self.settings.clone(),
+        }
     }
 
     fn with_params<T: ToString>(&self, key: &'static str, value: T) -> FirebaseParams {
-        FirebaseParams::new(&self.url, key, value)
+        FirebaseParams::new(self, key, value)
+    }
+}
+
+impl fmt::Debug for Firebase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Firebase")
+         .field("url", &redact(&self.url))
+         .field("path", &self.path)
+         .field("authed", &self.auth.is_some())
+         .finish()
+    }
+}
+
+/// Shows the url of the reference with any credentials in it redacted.
+impl fmt::Display for Firebase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&redact(&self.url))
     }
 }
 
@@ -406,7 +955,10 @@ impl Firebase {
 #[derive(Clone)]
 pub struct FirebaseParams {
     url: Arc<Url>,
-    params: HashMap<&'static str, String>,
+    // Sorted, so the same query always makes the same url.
+    params: BTreeMap<&'static str, String>,
+    auth: Option<Arc<TokenProvider>>,
+    settings: Settings,
 }
 
 impl FirebaseParams {
@@ -419,18 +971,32 @@ impl FirebaseParams {
     /// let first5 = alphabetic.get();
     /// ```
     pub fn get(&self) -> Result<Response, ReqErr> {
-        Firebase::request_url(&self.url, Method::GET, None)
+        Firebase::request_url(&self.url, self.auth.as_ref().map(|a| &**a), &self.settings, Method::GET, None)
+    }
+
+    /// Gets data from Firebase as a ```DataSnapshot```, with its
+    /// children in the order of the query, see ```order_by```.
+    pub fn get_snapshot(&self) -> Result<DataSnapshot, ReqErr> {
+        let res = try!( self.get() );
+        snapshot::from_response(&self.url, res, self.params.get(ORDER_BY).map(|o| &o[..]))
     }
 
     /// Asynchronous version of the get method, takes a callback
-    /// and returns a handle to the thread making the request to Firebase.
-    pub fn get_async<F>(&self, callback: F) -> JoinHandle<()>
-    where F: Fn(Result<Response, ReqErr>) + Send + 'static {
-        Firebase::request_url_async(&self.url, Method::GET, None, callback)
+    /// and returns a handle to the request.
+    pub fn get_async<F>(&self, callback: F) -> RequestHandle
+    where F: FnOnce(Result<Response, ReqErr>) + Send + 'static {
+        Firebase::request_url_async(&self.url, &self.auth, &self.settings, Method::GET, None, callback)
+    }
+
+    /// Starts the get request and returns a handle to its result,
+    /// see ```Firebase::get_handle```.
+    pub fn get_handle(&self) -> ResponseHandle {
+        Firebase::request_url_handle(&self.url, &self.auth, &self.settings, Method::GET, None)
     }
 
     /// Returns the current URL as a string that will be used
     /// to make the REST call when talking to Firebase.
+    /// Tokens are only added when a request is made, so they are not part of it.
     pub fn get_url(&self) -> String {
         self.url.serialize()
     }
@@ -491,6 +1057,44 @@ impl FirebaseParams {
         self.add_param(FORMAT, EXPORT)
     }
 
+    /// Modifies the current ```FirebaseParams``` instance
+    /// so the security rules see the ```auth``` variable as ```auth```
+    /// in every request, see ```Firebase::auth_variable_override```.
+    pub fn auth_variable_override(self, auth: &Json) -> Self {
+        self.add_param(AUTH_OVERRIDE, auth)
+    }
+
+    /// Asks the server to give up reads that take longer than ```limit```.
+    pub fn timeout(self, limit: Duration) -> Self {
+        self.add_param(TIMEOUT, server_timeout(limit))
+    }
+
+    /// Fails requests that take longer than ```timeout``` to connect,
+    /// see ```Firebase::connect_timeout```.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails requests that take longer than ```timeout``` in total,
+    /// see ```Firebase::request_timeout```.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Retries failed requests as told by ```policy```, see ```RetryPolicy```.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.settings.retry = Some(policy);
+        self
+    }
+
+    /// Cancels the requests with ```token```, see ```CancelToken```.
+    pub fn cancel_with(mut self, token: &CancelToken) -> Self {
+        self.settings.cancel = Some(token.clone());
+        self
+    }
+
     fn add_param<T: ToString>(mut self, key: &'static str, value: T) -> Self {
         let value = value.to_string();
         self.params.insert(key, value);
@@ -506,31 +1110,38 @@ impl FirebaseParams {
         self.url = Arc::new(url);
     }
 
-    fn get_auth(url: &Url) -> HashMap<&'static str, String> {
-        let mut pair: HashMap<&'static str, String> = HashMap::new();
+    // Parameters of the reference that every query has to keep.
+    fn get_kept_params(url: &Url) -> BTreeMap<&'static str, String> {
+        let mut pair: BTreeMap<&'static str, String> = BTreeMap::new();
 
         if let Some(queries) = url.query_pairs() {
             for &(ref k, ref v) in queries.iter() {
-                if k == AUTH {
-                    pair.insert(AUTH, v.to_string());
+                if k == AUTH_OVERRIDE {
+                    pair.insert(AUTH_OVERRIDE, v.to_string());
+                } else if k == NAMESPACE {
+                    pair.insert(NAMESPACE, v.to_string());
                 }
             }
         }
         pair
     }
 
-    fn new<T: ToString>(url: &Url, key: &'static str, value: T) -> Self {
+    fn new<T: ToString>(fb: &Firebase, key: &'static str, value: T) -> Self {
         let me = FirebaseParams {
-            url: Arc::new(url.clone()),
-            params: FirebaseParams::get_auth(&url),
+            url: fb.url.clone(),
+            params: FirebaseParams::get_kept_params(&fb.url),
+            auth: fb.auth.clone(),
+            settings: fb.settings.clone(),
         };
         me.add_param(key, value)
     }
 
-    fn from_ops(url: &Url, opts: &FbOps) -> Self {
+    fn from_ops(fb: &Firebase, opts: &FbOps) -> Self {
         let mut me = FirebaseParams {
-            url: Arc::new(url.clone()),
-            params: FirebaseParams::get_auth(&url),
+            url: fb.url.clone(),
+            params: FirebaseParams::get_kept_params(&fb.url),
+            auth: fb.auth.clone(),
+            settings: fb.settings.clone(),
         };
         if let Some(order) = opts.order_by {
             me.params.insert(ORDER_BY, order.to_string());
@@ -558,12 +1169,32 @@ impl FirebaseParams {
                 me.params.insert(FORMAT, EXPORT.to_string());
             }
         }
+        if let Some(limit) = opts.timeout {
+            me.params.insert(TIMEOUT, server_timeout(limit));
+        }
         // Copy all of the params into the url.
         me.set_params();
         me
     }
 }
 
+impl fmt::Debug for FirebaseParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FirebaseParams")
+         .field("url", &redact(&self.url))
+         .field("authed", &self.auth.is_some())
+         .finish()
+    }
+}
+
+/// Shows the url of the query with any credentials in it redacted.
+impl fmt::Display for FirebaseParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&redact(&self.url))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 enum Method {
     GET,
     POST,
@@ -582,6 +1213,78 @@ const SHALLOW:        &'static str = "shallow";
 const FORMAT:         &'static str = "format";
 const EXPORT:         &'static str = "export";
 const AUTH:           &'static str = "auth";
+const ACCESS_TOKEN:   &'static str = "access_token";
+const AUTH_OVERRIDE:  &'static str = "auth_variable_override";
+const TIMEOUT:        &'static str = "timeout";
+const NAMESPACE:      &'static str = "ns";
+const REDACTED:       &'static str = "<redacted>";
+
+const EMULATOR_HOST_VAR: &'static str = "FIREBASE_DATABASE_EMULATOR_HOST";
+const EMULATOR_OWNER:    &'static str = "owner";
+
+/// The longest read the server allows, in milliseconds.
+const MAX_SERVER_TIMEOUT: u64 = 15 * 60 * 1000;
+/// ```CURLE_OPERATION_TIMEDOUT```, curl does not export its error codes.
+const CURL_TIMED_OUT: i32 = 28;
+
+const OK:           u32 = 200;
+const NOT_MODIFIED: u32 = 304;
+
+/// The connect timeout used when none is set.
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30 * 1000;
+/// Tells curl not to limit the time of a request.
+const NO_TIMEOUT: u64 = 0;
+/// Without a request timeout, requests fail once no data came in for this long,
+/// on top of the time the server may take for a read. Large exports can
+/// take as long as they need, but a server that stopped answering can't
+/// hang a request forever.
+const DEFAULT_STALL_TIMEOUT: u64 = 30 * 1000;
+
+/// How a reference makes its requests, everything created
+/// from a reference shares its settings.
+#[derive(Clone, Debug, Default)]
+struct Settings {
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    retry:           Option<RetryPolicy>,
+    cancel:          Option<CancelToken>,
+    cache:           Option<Arc<Cache>>,
+    queue:           Option<Arc<WriteQueue>>,
+    store:           Option<Arc<SnapshotStore>>,
+    client:          Arc<Client>,
+}
+
+impl Settings {
+    /// Sets every option on a pooled handle, so nothing
+    /// is left over from the request it made before.
+    fn apply(&self, handle: http::Handle, url: &Url) -> http::Handle {
+        let connect = self.connect_timeout.map(timeout_millis).unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let total = self.request_timeout.map(timeout_millis).unwrap_or(NO_TIMEOUT);
+        // Curl counts the stall in whole seconds, and only checks it with a speed limit.
+        let (limit, stall) = match self.stall_timeout(url) {
+            Some(stall) => (1, (timeout_millis(stall) + 999) / 1000),
+            None        => (0, 0),
+        };
+        handle.connect_timeout(connect as usize)
+              .timeout(total as usize)
+              .low_speed_limit(limit)
+              .low_speed_timeout(stall as usize)
+    }
+
+    /// How long a request to ```url``` may go without receiving any data,
+    /// if it has no request timeout. Reads asking the server for a longer
+    /// ```timeout``` than the default get that much longer.
+    fn stall_timeout(&self, url: &Url) -> Option<Duration> {
+        if self.request_timeout.is_some() {
+            return None;
+        }
+        let server = url.query_pairs().unwrap_or(Vec::new()).into_iter()
+                        .find(|&(ref k, _)| k == TIMEOUT)
+                        .and_then(|(_, v)| v.trim_right_matches("ms").parse().ok())
+                        .unwrap_or(0);
+        Some(Duration::from_millis(DEFAULT_STALL_TIMEOUT + server))
+    }
+}
 
 #[derive(Debug)]
 pub struct FbOps<'l> {
@@ -593,6 +1296,7 @@ pub struct FbOps<'l> {
     pub equal_to:       Option<u32>,
     pub shallow:        Option<bool>,
     pub format:         Option<bool>,
+    pub timeout:        Option<Duration>,
 }
 
 impl<'l> Default for FbOps<'l> {
@@ -606,6 +1310,7 @@ impl<'l> Default for FbOps<'l> {
             equal_to:       None,
             shallow:        None,
             format:         None,
+            timeout:        None,
         }
     }
 }
@@ -614,20 +1319,107 @@ impl<'l> Default for FbOps<'l> {
 pub enum ReqErr {
     ReqNotJSON,
     RespNotUTF8(str::Utf8Error),
+    /// The response body could not be decompressed from its ```Content-Encoding```.
+    RespNotDecodable(String),
+    /// The response body decompressed to more than
+    /// ```Client::max_decoded_size```, this many bytes.
+    RespTooLarge(usize),
     NetworkErr(curl::ErrCode),
+    AuthErr(AuthError),
+    /// The request did not finish within its connect or request timeout.
+    Timeout,
+    /// The ```Executor``` dropped the request without running it.
+    Rejected,
+    /// A request made by the ```nonblocking``` transport failed to connect or send.
+    TransportErr(String),
+    /// The request can't be made the way it was configured, e.g. through a
+    /// proxy by the ```nonblocking``` transport. Nothing was sent.
+    Unsupported(&'static str),
+    /// The server refused to open a listener or to give a snapshot,
+    /// with this response.
+    Refused(Response),
+    /// The response to a snapshot was not JSON.
+    RespNotJSON(BuilderError),
+    /// The request was cancelled by its ```CancelToken```.
+    Cancelled,
+    /// The server could not be reached, the write was
+    /// queued to be replayed, see ```WriteQueue```.
+    Queued,
+    /// The server could not be reached, and the write
+    /// could not be added to the journal of its ```WriteQueue```.
+    QueueErr(io::Error),
+}
+
+impl ReqErr {
+    fn from_curl(err: curl::ErrCode) -> Self {
+        if err.code() as i32 == CURL_TIMED_OUT {
+            ReqErr::Timeout
+        } else {
+            ReqErr::NetworkErr(err)
+        }
+    }
+
+    /// Whether the server couldn't be reached, also while fetching a token,
+    /// rather than answering. Writes are queued and reads answered from the
+    /// snapshot store on these.
+    fn is_offline(&self) -> bool {
+        match *self {
+            ReqErr::NetworkErr(_) | ReqErr::Timeout | ReqErr::TransportErr(_) => true,
+            ReqErr::AuthErr(AuthError::NetworkErr(_)) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ParseError {
     UrlHasNoPath,
     UrlIsNotHTTPS,
+    UrlHasNoNamespace,
     Parser(url::ParseError),
 }
 
+// Errors never carry a url or a token, so they are safe to log as they are.
+impl fmt::Display for ReqErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReqErr::ReqNotJSON         => f.write_str("request data is not JSON"),
+            ReqErr::RespNotUTF8(ref e) => write!(f, "response is not UTF-8: {}", e),
+            ReqErr::RespNotDecodable(ref e) => write!(f, "response could not be decoded from {:?}", e),
+            ReqErr::RespTooLarge(limit) => write!(f, "response decompressed to more than {} bytes", limit),
+            ReqErr::NetworkErr(ref e)  => write!(f, "network error: {}", e),
+            ReqErr::AuthErr(ref e)     => write!(f, "authentication failed: {}", e),
+            ReqErr::Timeout            => f.write_str("request timed out"),
+            ReqErr::Rejected           => f.write_str("request rejected by the executor"),
+            ReqErr::TransportErr(ref e) => write!(f, "transport error: {}", e),
+            ReqErr::Unsupported(e)     => write!(f, "not supported: {}", e),
+            ReqErr::Refused(ref res)   => write!(f, "request refused with status {}", res.code),
+            ReqErr::RespNotJSON(ref e) => write!(f, "response is not JSON: {}", e),
+            ReqErr::Cancelled          => f.write_str("request cancelled"),
+            ReqErr::Queued             => f.write_str("server unreachable, write queued"),
+            ReqErr::QueueErr(ref e)    => write!(f, "server unreachable, write not queued: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UrlHasNoPath      => f.write_str("url has no path"),
+            ParseError::UrlIsNotHTTPS     => f.write_str("url is not https"),
+            ParseError::UrlHasNoNamespace => f.write_str("url names no database for the emulator"),
+            ParseError::Parser(ref e)     => write!(f, "invalid url: {}", e),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Response {
     pub body: String,
     pub code: u32,
+    /// The body is the last known data from a ```SnapshotStore```,
+    /// because the server could not be reached.
+    pub stale: bool,
 }
 
 impl Response {
@@ -649,6 +1441,7 @@ impl Response {
     /// let response = Response {
     ///     body: "324567898".to_string(),
     ///     code: 200,
+    ///     stale: false,
     /// };
     ///
     /// let parsed: u32 = response.parse().unwrap();
@@ -659,6 +1452,78 @@ impl Response {
     }
 }
 
+/// Whole milliseconds, rounded up.
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + ((duration.subsec_nanos() + 999_999) / 1_000_000) as u64
+}
+
+/// A timeout in milliseconds for curl, at least 1, because 0 turns it off.
+fn timeout_millis(timeout: Duration) -> u64 {
+    cmp::max(millis(timeout), 1)
+}
+
+/// Formats the REST ```timeout``` parameter, e.g. ```2500ms```.
+fn server_timeout(limit: Duration) -> String {
+    format!("{}ms", cmp::min(millis(limit), MAX_SERVER_TIMEOUT))
+}
+
+/// Asks ```auth``` for a token and attaches it to the request, either as
+/// a parameter of the returned url or as the returned ```Authorization``` header.
+fn authorize<'u>(url: &'u Url, auth: Option<&TokenProvider>) -> Result<(Cow<'u, Url>, Option<String>), ReqErr> {
+    let mut url = Cow::Borrowed(url);
+    let mut bearer = None;
+
+    if let Some(auth) = auth {
+        let token = match auth.token() {
+            Ok(t)  => t,
+            Err(e) => return Err(ReqErr::AuthErr(e)),
+        };
+        let param = match token {
+            AuthToken::Auth(t)        => Some((AUTH, t)),
+            AuthToken::AccessToken(t) => Some((ACCESS_TOKEN, t)),
+            AuthToken::Bearer(t)      => {
+                bearer = Some(format!("Bearer {}", t));
+                None
+            },
+        };
+        if let Some((key, token)) = param {
+            let mut pairs = url.query_pairs().unwrap_or(Vec::new());
+            pairs.push((key.to_string(), token));
+            url.to_mut().set_query_from_pairs(pairs.into_iter());
+        }
+    }
+    Ok((url, bearer))
+}
+
+fn is_credential(param: &str) -> bool {
+    param == AUTH || param == ACCESS_TOKEN
+}
+
+/// Serializes a url for logs and debug output, the values of credential
+/// parameters and any password are replaced by ```<redacted>```.
+fn redact(url: &Url) -> String {
+    let mut url = url.clone();
+    let pairs = url.query_pairs();
+    url.query = None;
+    if let Some(password) = url.password_mut() {
+        *password = REDACTED.to_string();
+    }
+
+    let mut redacted = url.serialize();
+    if let Some(pairs) = pairs {
+        let params: Vec<String> = pairs.iter().map(|&(ref k, ref v)| {
+            if is_credential(k) {
+                form_urlencoded::serialize(vec![(k as &str, "")]) + REDACTED
+            } else {
+                form_urlencoded::serialize(vec![(k as &str, v as &str)])
+            }
+        }).collect();
+        redacted.push('?');
+        redacted.push_str(&params.join("&"));
+    }
+    redacted
+}
+
 fn parse(url: &str) -> Result<Url, ParseError> {
     match Url::parse(&url) {
         Ok(u)  => Ok(u),
@@ -666,6 +1531,20 @@ fn parse(url: &str) -> Result<Url, ParseError> {
     }
 }
 
+/// The name of the database a url points to, ```myfb``` for both
+/// ```https://myfb.firebaseio.com``` and ```http://localhost:9000/.json?ns=myfb```.
+fn namespace_of(url: &Url) -> Result<String, ParseError> {
+    if let Some(pairs) = url.query_pairs() {
+        if let Some(&(_, ref ns)) = pairs.iter().find(|&&(ref k, _)| k == NAMESPACE) {
+            return Ok(ns.clone());
+        }
+    }
+    match url.domain().and_then(|d| d.split('.').next()) {
+        Some(label) if !label.is_empty() && url.domain() != Some(label) => Ok(label.to_string()),
+        _ => Err(ParseError::UrlHasNoNamespace),
+    }
+}
+
 fn unwrap_path(url: &Url) -> Result<&[String], ParseError> {
     match url.path() {
         None    => return Err(ParseError::UrlHasNoPath),
@@ -0,0 +1,711 @@
+/*!
+ Futures based requests and listeners, enabled by the ```nonblocking``` feature.
+
+ A ```nonblocking::Firebase``` is made from a ```firebase::Firebase``` and shares its
+ url, credentials, timeouts and retry policy. Its requests return futures and are
+ made with hyper, so they must be run on a tokio runtime.
+
+ ```no_run
+ # extern crate firebase;
+ # extern crate tokio;
+ # fn main() {
+ let fb = firebase::Firebase::new("https://shows.firebaseio.com").unwrap().nonblocking();
+ let episode = fb.at("/futurama/episodes/140").unwrap();
+
+ let runtime = tokio::runtime::Runtime::new().unwrap();
+ let info = runtime.block_on(episode.get());
+ # }
+ ```
+ */
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use futures_util::future::{self, BoxFuture, Either, FutureExt};
+use futures_util::stream::Stream;
+use hyper::{self, Body, Request, StatusCode};
+use hyper::body::{Bytes, HttpBody};
+use rustc_serialize::json::Json;
+use tokio::runtime::{self, Runtime};
+use tokio::time;
+use url::Url;
+
+use cancel::{self, CancelToken};
+use client::{self, Transport};
+use encoding;
+use path;
+use retry;
+use store;
+use {authorize, is_credential, DbPath, Method, ParseError, ReqErr, Response, Settings, SnapshotStore, TokenProvider};
+
+const EVENT_STREAM:  &'static str = "text/event-stream";
+const MAX_REDIRECTS: u32 = 5;
+
+impl ::Firebase {
+    /// Creates a reference to the same location whose requests return futures.
+    pub fn nonblocking(&self) -> Firebase {
+        Firebase {
+            inner: self.clone(),
+        }
+    }
+
+    /// Makes a get request and returns a reader of the body as it comes in,
+    /// so large data can be processed or written out without keeping all
+    /// of it in memory. The request timeout applies to receiving the head
+    /// of the response and every read after that, without one they fail
+    /// once no data came in for 30 seconds.
+    ///
+    /// Only available with the ```nonblocking``` feature, as the curl bindings
+    /// hand over the body once all of it is received. It is meant for large
+    /// one-off reads like exports, and is a plain request unlike ```get```:
+    ///
+    /// - it isn't retried, doesn't use the cache or snapshot store of the
+    ///   reference, and a refused token isn't fetched again,
+    /// - it fails with ```ReqErr::Unsupported``` if the client has a proxy for
+    ///   the url, as the transport it uses can't go through one,
+    /// - it fails with ```ReqErr::Unsupported``` when called inside a tokio
+    ///   runtime, as the reader drives a runtime of its own. Reading from it
+    ///   inside one fails the same way, use ```spawn_blocking``` there.
+    ///
+    /// Both errors are returned before anything is sent, or a token fetched.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use firebase::*;
+    /// use std::fs::File;
+    /// use std::io;
+    ///
+    /// let firebase = Firebase::new("https://shows.firebaseio.com").unwrap();
+    /// let mut export = firebase.at("/futurama").unwrap().get_reader().unwrap();
+    /// io::copy(&mut export, &mut File::create("futurama.json").unwrap()).unwrap();
+    /// ```
+    pub fn get_reader(&self) -> Result<ResponseReader, ReqErr> {
+        ResponseReader::new(&self.url, self.auth.as_ref().map(|a| &**a), &self.settings)
+    }
+}
+
+impl ::FirebaseParams {
+    /// Creates a query with the same parameters whose requests return futures.
+    pub fn nonblocking(&self) -> Query {
+        Query {
+            inner: self.clone(),
+        }
+    }
+
+    /// Makes the get request and returns a reader of the body as it
+    /// comes in, see ```Firebase::get_reader```.
+    pub fn get_reader(&self) -> Result<ResponseReader, ReqErr> {
+        ResponseReader::new(&self.url, self.auth.as_ref().map(|a| &**a), &self.settings)
+    }
+}
+
+/// A Firebase reference whose requests return futures,
+/// see ```firebase::Firebase``` for what every method does.
+#[derive(Clone, Debug)]
+pub struct Firebase {
+    inner: ::Firebase,
+}
+
+impl Firebase {
+    /// Same as ```firebase::Firebase::new```.
+    pub fn new(url: &str) -> Result<Self, ParseError> {
+        ::Firebase::new(url).map(|fb| fb.nonblocking())
+    }
+
+    pub fn at(&self, add_path: &str) -> Result<Self, ParseError> {
+        self.inner.at(add_path).map(|fb| fb.nonblocking())
+    }
+
+    pub fn at_path(&self, add_path: &DbPath) -> Self {
+        self.inner.at_path(add_path).nonblocking()
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        self.inner.parent().map(|fb| fb.nonblocking())
+    }
+
+    pub fn root(&self) -> Self {
+        self.inner.root().nonblocking()
+    }
+
+    pub fn key(&self) -> Option<String> {
+        self.inner.key()
+    }
+
+    pub fn path(&self) -> &DbPath {
+        self.inner.path()
+    }
+
+    pub fn get_url(&self) -> String {
+        self.inner.get_url()
+    }
+
+    /// Returns the blocking reference to the same location.
+    pub fn blocking(&self) -> &::Firebase {
+        &self.inner
+    }
+
+    pub fn get(&self) -> ResponseFuture {
+        self.request(Method::GET, None)
+    }
+
+    pub fn set<S: Into<String>>(&self, data: S) -> ResponseFuture {
+        self.request(Method::PUT, Some(data.into()))
+    }
+
+    pub fn push<S: Into<String>>(&self, data: S) -> ResponseFuture {
+        self.request(Method::POST, Some(data.into()))
+    }
+
+    pub fn update<S: Into<String>>(&self, data: S) -> ResponseFuture {
+        self.request(Method::PATCH, Some(data.into()))
+    }
+
+    pub fn remove(&self) -> ResponseFuture {
+        self.request(Method::DELETE, None)
+    }
+
+    /// Listens for changes to the data at this location, see ```Event```.
+    /// The stream ends when the server closes it, e.g. after a ```Cancel```.
+    pub fn listen(&self) -> EventStream {
+        EventStream::new(&self.inner.url, &self.inner.auth, &self.inner.settings)
+    }
+
+    fn request(&self, method: Method, data: Option<String>) -> ResponseFuture {
+        ResponseFuture::new(&self.inner.url, &self.inner.auth, &self.inner.settings, method, data)
+    }
+}
+
+/// A query whose requests return futures, see ```firebase::FirebaseParams```.
+#[derive(Clone, Debug)]
+pub struct Query {
+    inner: ::FirebaseParams,
+}
+
+impl Query {
+    pub fn get(&self) -> ResponseFuture {
+        ResponseFuture::new(&self.inner.url, &self.inner.auth, &self.inner.settings, Method::GET, None)
+    }
+
+    /// Listens for changes to the data matching this query.
+    pub fn listen(&self) -> EventStream {
+        EventStream::new(&self.inner.url, &self.inner.auth, &self.inner.settings)
+    }
+
+    pub fn get_url(&self) -> String {
+        self.inner.get_url()
+    }
+}
+
+/// The response to a request, once it has been made. Retries, timeouts
+/// and cancellation are handled the same way as for blocking requests.
+pub struct ResponseFuture {
+    inner:  BoxFuture<'static, Result<Response, ReqErr>>,
+    cancel: Option<Cancellation>,
+}
+
+impl ResponseFuture {
+    fn new(url: &Arc<Url>, auth: &Option<Arc<TokenProvider>>, settings: &Settings,
+           method: Method, data: Option<String>) -> Self {
+        let inner = match client::transport(&settings.client, url) {
+            Ok(transport) => request(transport, url.clone(), auth.clone(), settings.clone(), method, data, 0),
+            Err(e)        => future::ready(Err(e)).boxed(),
+        };
+        let inner = match (method, settings.store.clone()) {
+            (Method::GET, Some(store)) => {
+                let url = url.clone();
+                inner.map(move |result| store::read_through(&store, &url, result)).boxed()
+            },
+            _ => inner,
+        };
+        // Only a read is dropped when cancelled, a write has to tell if it went through.
+        let cancel = match method {
+            Method::GET => settings.cancel.as_ref().map(Cancellation::new),
+            _           => None,
+        };
+        ResponseFuture {
+            inner:  inner,
+            cancel: cancel,
+        }
+    }
+}
+
+impl Future for ResponseFuture {
+    type Output = Result<Response, ReqErr>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(ref mut cancel) = self.cancel {
+            if cancel.poll(cx) {
+                return Poll::Ready(Err(ReqErr::Cancelled));
+            }
+        }
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+/// The body of a get request, read as it is received, see ```Firebase::get_reader```.
+/// Reading it blocks, it is driven by a runtime of its own.
+pub struct ResponseReader {
+    /// The status code of the response.
+    pub code: u32,
+    body:    Body,
+    chunk:   Bytes,
+    timeout: Option<Duration>,
+    cancel:  Option<CancelToken>,
+    /// Only taken when the reader is dropped.
+    runtime: Option<Runtime>,
+}
+
+impl ResponseReader {
+    fn new(url: &Url, auth: Option<&TokenProvider>, settings: &Settings) -> Result<Self, ReqErr> {
+        if is_cancelled(settings) {
+            return Err(ReqErr::Cancelled);
+        }
+        // Everything the reader doesn't support fails before the token is fetched.
+        try!( outside_runtime() );
+        let transport = try!( client::unpooled_transport(&settings.client, url) );
+        let runtime = try!( runtime::Builder::new_current_thread().enable_all().build()
+                                                                 .map_err(|e| ReqErr::TransportErr(e.to_string())) );
+        let req = try!( build_request(url, auth, Method::GET, None, None) );
+        let timeout = settings.request_timeout.or(settings.stall_timeout(url));
+
+        // Timers can only be created inside the runtime.
+        let res = match runtime.block_on(future::lazy(|_| within(timeout, transport.request(req))).flatten()) {
+            Some(Ok(res)) => res,
+            Some(Err(e))  => return Err(ReqErr::TransportErr(e.to_string())),
+            None          => return Err(ReqErr::Timeout),
+        };
+        Ok(ResponseReader {
+            code:    res.status().as_u16() as u32,
+            body:    res.into_body(),
+            chunk:   Bytes::new(),
+            timeout: timeout,
+            cancel:  settings.cancel.clone(),
+            runtime: Some(runtime),
+        })
+    }
+}
+
+impl Drop for ResponseReader {
+    /// Dropping a runtime the usual way panics inside another one.
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Blocking on a runtime inside another one panics.
+fn outside_runtime() -> Result<(), ReqErr> {
+    match runtime::Handle::try_current() {
+        Ok(_)  => Err(ReqErr::Unsupported("the reader can't block inside a tokio runtime")),
+        Err(_) => Ok(()),
+    }
+}
+
+impl Read for ResponseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            if self.cancel.as_ref().map_or(false, |c| c.is_cancelled()) {
+                return Err(io::Error::new(io::ErrorKind::Other, ReqErr::Cancelled.to_string()));
+            }
+            try!( outside_runtime().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())) );
+            let (body, timeout) = (&mut self.body, self.timeout);
+            let runtime = self.runtime.as_ref().unwrap();
+            match runtime.block_on(future::lazy(|_| within(timeout, body.data())).flatten()) {
+                Some(Some(Ok(chunk))) => self.chunk = chunk,
+                Some(Some(Err(e)))    => return Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+                Some(None)            => return Ok(0),
+                None                  => return Err(io::Error::new(io::ErrorKind::TimedOut, ReqErr::Timeout.to_string())),
+            }
+        }
+        let len = cmp::min(buf.len(), self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+/// A change to the data under a listened location.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The data at the path, relative to the listened location, was replaced.
+    /// The first event of every listener is a ```Put``` of all of the data.
+    Put(DbPath, Json),
+    /// The children of the data at the path were updated.
+    Patch(DbPath, Json),
+    /// Sent by the server every 30 seconds to keep the connection open.
+    KeepAlive,
+    /// The listener was cancelled by the server, e.g. because the
+    /// security rules no longer allow reading the location.
+    Cancel(String),
+    /// The token of the listener expired, listen again with a new one.
+    AuthRevoked(String),
+}
+
+/// The events of a listener, see ```Firebase::listen```.
+pub struct EventStream {
+    state:   State,
+    buffer:  Vec<u8>,
+    pending: VecDeque<Event>,
+    cancel:  Option<Cancellation>,
+    /// Where to keep the data of the listened url up to date.
+    store:   Option<(Arc<SnapshotStore>, Url)>,
+}
+
+enum State {
+    Connecting(BoxFuture<'static, Result<Body, ReqErr>>),
+    Reading(Body),
+    Done,
+}
+
+impl EventStream {
+    fn new(url: &Url, auth: &Option<Arc<TokenProvider>>, settings: &Settings) -> Self {
+        let limit = client::max_decoded(&settings.client);
+        let connecting = match client::transport(&settings.client, url) {
+            Ok(transport) => connect(transport, url.clone(), auth.clone(), limit, 0),
+            Err(e)        => future::ready(Err(e)).boxed(),
+        };
+        EventStream {
+            state:   State::Connecting(connecting),
+            buffer:  Vec::new(),
+            pending: VecDeque::new(),
+            cancel:  settings.cancel.as_ref().map(Cancellation::new),
+            store:   settings.store.clone().map(|store| (store, url.clone())),
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event, ReqErr>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let cancelled = match this.cancel {
+            Some(ref mut cancel) => cancel.poll(cx),
+            None                 => false,
+        };
+        if cancelled {
+            // Ends the stream after this error.
+            this.cancel = None;
+            this.state = State::Done;
+            this.pending.clear();
+            return Poll::Ready(Some(Err(ReqErr::Cancelled)));
+        }
+
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                if let Some((ref store, ref url)) = this.store {
+                    // A failure to save only means the data can't be read offline.
+                    let _ = match event {
+                        Event::Put(ref path, ref data)   => store::apply(store, url, path, data, false),
+                        Event::Patch(ref path, ref data) => store::apply(store, url, path, data, true),
+                        _ => Ok(()),
+                    };
+                }
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            let next = match this.state {
+                State::Connecting(ref mut connecting) => match connecting.as_mut().poll(cx) {
+                    Poll::Pending         => return Poll::Pending,
+                    Poll::Ready(Ok(body)) => State::Reading(body),
+                    Poll::Ready(Err(e))   => {
+                        this.state = State::Done;
+                        return Poll::Ready(Some(Err(e)));
+                    },
+                },
+                State::Reading(ref mut body) => match Pin::new(body).poll_data(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        this.buffer.extend_from_slice(&chunk);
+                        parse_events(&mut this.buffer, &mut this.pending);
+                        continue;
+                    },
+                    Poll::Ready(Some(Err(e))) => {
+                        this.state = State::Done;
+                        return Poll::Ready(Some(Err(ReqErr::TransportErr(e.to_string()))));
+                    },
+                    Poll::Ready(None) => State::Done,
+                },
+                State::Done => return Poll::Ready(None),
+            };
+            this.state = next;
+        }
+    }
+}
+
+/// Wakes the task polling it once its token is cancelled.
+struct Cancellation {
+    token: CancelToken,
+    waker: Arc<Mutex<Option<Waker>>>,
+    hook:  Option<u64>,
+}
+
+impl Cancellation {
+    fn new(token: &CancelToken) -> Self {
+        Cancellation {
+            token: token.clone(),
+            waker: Arc::new(Mutex::new(None)),
+            hook:  None,
+        }
+    }
+
+    /// Returns whether the token is cancelled, or makes sure
+    /// the task is woken up when it is.
+    fn poll(&mut self, cx: &mut Context) -> bool {
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        if self.hook.is_none() {
+            let waker = self.waker.clone();
+            self.hook = cancel::on_cancel(&self.token, move || {
+                if let Some(waker) = waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            });
+        }
+        self.token.is_cancelled()
+    }
+}
+
+impl Drop for Cancellation {
+    fn drop(&mut self) {
+        if let Some(id) = self.hook {
+            cancel::forget(&self.token, id);
+        }
+    }
+}
+
+/// Makes a request with retries, cancelled like ```Firebase::request_retrying```.
+fn request(transport: Transport, url: Arc<Url>, auth: Option<Arc<TokenProvider>>, settings: Settings,
+           method: Method, data: Option<String>, retries: u32) -> BoxFuture<'static, Result<Response, ReqErr>> {
+    let timeout = settings.request_timeout;
+
+    // Nothing happens until the future is polled, which makes sure the
+    // timer is created on a runtime and the token is fetched when needed.
+    future::lazy(move |_| ()).then(move |_| {
+        let attempt = if is_cancelled(&settings) {
+            Either::Left(future::ready(Some((Err(ReqErr::Cancelled), None))))
+        } else {
+            let stall = settings.stall_timeout(&url);
+            let limit = client::max_decoded(&settings.client);
+            let auth = auth.as_ref().map(|a| &**a);
+            let attempt = send(&transport, &url, auth, method, data.clone(), stall, limit);
+            Either::Right(within(timeout, attempt))
+        };
+        attempt.map(move |attempt| (attempt, transport, url, auth, settings, data))
+    }).then(move |(attempt, transport, url, auth, settings, data)| {
+        let (result, retry_after) = attempt.unwrap_or((Err(ReqErr::Timeout), None));
+        let delay = match settings.retry {
+            Some(ref policy) => retry::next_delay(policy, &method, retries, &result, retry_after),
+            None             => None,
+        };
+        match delay {
+            Some(delay) => Either::Left(time::sleep(delay).then(move |_| {
+                match (is_cancelled(&settings), method) {
+                    (true, Method::GET) => future::ready(Err(ReqErr::Cancelled)).boxed(),
+                    // A write that was sent ends with the result of its last attempt.
+                    (true, _)           => future::ready(result).boxed(),
+                    (false, _)          => request(transport, url, auth, settings, method, data, retries + 1),
+                }
+            })),
+            None => Either::Right(future::ready(result)),
+        }
+    }).boxed()
+}
+
+fn is_cancelled(settings: &Settings) -> bool {
+    settings.cancel.as_ref().map_or(false, |c| c.is_cancelled())
+}
+
+/// Ends ```future``` with ```None``` once ```timeout``` runs out, if there is one.
+/// Like ```time::timeout```, this must be called on a runtime.
+fn within<F: Future>(timeout: Option<Duration>, future: F) -> impl Future<Output = Option<F::Output>> {
+    match timeout {
+        Some(timeout) => Either::Left(time::timeout(timeout, future).map(|result| result.ok())),
+        None          => Either::Right(future.map(Some)),
+    }
+}
+
+/// Makes a single attempt at a request, like ```Firebase::send```.
+/// Without a request timeout, it gives up once no data came in for ```stall```.
+fn send(transport: &Transport, url: &Url, auth: Option<&TokenProvider>, method: Method,
+        data: Option<String>, stall: Option<Duration>,
+        limit: usize) -> BoxFuture<'static, (Result<Response, ReqErr>, Option<Duration>)> {
+    let mut req = match build_request(url, auth, method, data, None) {
+        Ok(r)  => r,
+        Err(e) => return future::ready((Err(e), None)).boxed(),
+    };
+    // Listeners are read as they come in, so only these are compressed.
+    req.headers_mut().insert("Accept-Encoding", hyper::header::HeaderValue::from_static(encoding::ACCEPT_ENCODING));
+
+    within(stall, transport.request(req)).then(move |res| {
+        let res = res.map(|res| res.map_err(|e| ReqErr::TransportErr(e.to_string())))
+                     .unwrap_or(Err(ReqErr::Timeout));
+        let res = match res {
+            Ok(r)  => r,
+            Err(e) => return Either::Left(future::ready((Err(e), None))),
+        };
+        let retry_after = res.headers().get("retry-after")
+                             .and_then(|value| value.to_str().ok())
+                             .and_then(retry::parse_retry_after);
+        Either::Right(read_response(res, stall, limit).map(move |result| (result, retry_after)))
+    }).boxed()
+}
+
+/// Opens a listener, following the redirects the server sends
+/// to point it at the right database server.
+fn connect(transport: Transport, url: Url, auth: Option<Arc<TokenProvider>>,
+           limit: usize, redirects: u32) -> BoxFuture<'static, Result<Body, ReqErr>> {
+    let req = match build_request(&url, auth.as_ref().map(|a| &**a), Method::GET, None, Some(EVENT_STREAM)) {
+        Ok(r)  => r,
+        Err(e) => return future::ready(Err(e)).boxed(),
+    };
+
+    transport.clone().request(req).then(move |res| {
+        let res = match res {
+            Ok(r)  => r,
+            Err(e) => return Either::Left(future::ready(Err(ReqErr::TransportErr(e.to_string())))),
+        };
+        if res.status() == StatusCode::OK {
+            return Either::Left(future::ready(Ok(res.into_body())));
+        }
+
+        let location = res.headers().get("location")
+                          .and_then(|value| value.to_str().ok())
+                          .and_then(|value| Url::parse(value).ok());
+        match location {
+            Some(location) if res.status().is_redirection() && redirects < MAX_REDIRECTS => {
+                // The token is attached again to the new url.
+                let location = without_credentials(location);
+                Either::Right(Either::Left(connect(transport, location, auth, limit, redirects + 1)))
+            },
+            _ => Either::Right(Either::Right(read_response(res, None, limit).map(|res| {
+                match res {
+                    Ok(res) => Err(ReqErr::Refused(res)),
+                    Err(e)  => Err(e),
+                }
+            }))),
+        }
+    }).boxed()
+}
+
+fn build_request(url: &Url, auth: Option<&TokenProvider>, method: Method,
+                 data: Option<String>, accept: Option<&str>) -> Result<Request<Body>, ReqErr> {
+    let (url, bearer) = try!( authorize(url, auth) );
+    let method = match method {
+        Method::GET    => hyper::Method::GET,
+        Method::POST   => hyper::Method::POST,
+        Method::PUT    => hyper::Method::PUT,
+        Method::PATCH  => hyper::Method::PATCH,
+        Method::DELETE => hyper::Method::DELETE,
+    };
+
+    let mut req = Request::builder().method(method).uri(url.serialize());
+    if let Some(bearer) = bearer {
+        req = req.header("Authorization", bearer);
+    }
+    if let Some(accept) = accept {
+        req = req.header("Accept", accept);
+    }
+    req.body(data.map(Body::from).unwrap_or(Body::empty()))
+       .map_err(|e| ReqErr::TransportErr(e.to_string()))
+}
+
+/// Reads the whole body of ```res```, giving up once no data came in for ```stall```,
+/// and decompresses it to at most ```limit``` bytes.
+fn read_response(res: hyper::Response<Body>, stall: Option<Duration>,
+                 limit: usize) -> BoxFuture<'static, Result<Response, ReqErr>> {
+    let code = res.status().as_u16() as u32;
+    let content_encoding = res.headers().get("content-encoding")
+                              .map(|value| value.to_str().unwrap_or("unknown").to_string());
+    read_body(res.into_body(), Vec::new(), stall).map(move |body| {
+        let body = try!( body );
+        let body = try!( encoding::decode(content_encoding.as_ref().map(|e| &e[..]), &body, limit) );
+        match str::from_utf8(&body) {
+            Ok(body) => Ok(Response { body: body.to_string(), code: code, stale: false }),
+            Err(e)   => Err(ReqErr::RespNotUTF8(e)),
+        }
+    }).boxed()
+}
+
+fn read_body(body: Body, mut bytes: Vec<u8>,
+             stall: Option<Duration>) -> BoxFuture<'static, Result<Vec<u8>, ReqErr>> {
+    // Hands the body back with the chunk, so the next one can be read after it.
+    let mut body = Some(body);
+    let next = future::poll_fn(move |cx| {
+        let chunk = match Pin::new(body.as_mut().unwrap()).poll_data(cx) {
+            Poll::Ready(chunk) => chunk,
+            Poll::Pending      => return Poll::Pending,
+        };
+        Poll::Ready((chunk, body.take().unwrap()))
+    });
+    within(stall, next).then(move |next| match next {
+        Some((Some(Ok(chunk)), body)) => {
+            bytes.extend_from_slice(&chunk);
+            Either::Left(read_body(body, bytes, stall))
+        },
+        Some((Some(Err(e)), _)) => Either::Right(future::ready(Err(ReqErr::TransportErr(e.to_string())))),
+        Some((None, _))         => Either::Right(future::ready(Ok(bytes))),
+        None                    => Either::Right(future::ready(Err(ReqErr::Timeout))),
+    }).boxed()
+}
+
+fn without_credentials(mut url: Url) -> Url {
+    if let Some(pairs) = url.query_pairs() {
+        let rest: Vec<_> = pairs.into_iter().filter(|&(ref k, _)| !is_credential(k)).collect();
+        if rest.is_empty() {
+            url.query = None;
+        } else {
+            url.set_query_from_pairs(rest.into_iter());
+        }
+    }
+    url
+}
+
+/// Takes every complete server-sent event out of ```buffer```.
+fn parse_events(buffer: &mut Vec<u8>, events: &mut VecDeque<Event>) {
+    loop {
+        let end = match buffer.windows(2).position(|w| w == b"\n\n") {
+            Some(end) => end,
+            None      => return,
+        };
+        let block: Vec<u8> = buffer.drain(..end + 2).collect();
+        let block = String::from_utf8_lossy(&block);
+
+        let mut name = "";
+        let mut data = String::new();
+        for line in block.lines() {
+            if line.starts_with("event:") {
+                name = line[6..].trim();
+            } else if line.starts_with("data:") {
+                data.push_str(line[5..].trim());
+            }
+        }
+        if let Some(event) = to_event(name, &data) {
+            events.push_back(event);
+        }
+    }
+}
+
+fn to_event(name: &str, data: &str) -> Option<Event> {
+    let json = Json::from_str(data).unwrap_or(Json::Null);
+    let reason = || json.as_string().map(|s| s.to_string()).unwrap_or(String::new());
+
+    match name {
+        "put" | "patch" => {
+            let path = path::from_keys(json.find("path").and_then(|p| p.as_string()).unwrap_or("/"));
+            let data = json.find("data").cloned().unwrap_or(Json::Null);
+            Some(if name == "put" { Event::Put(path, data) } else { Event::Patch(path, data) })
+        },
+        "keep-alive"   => Some(Event::KeepAlive),
+        "cancel"       => Some(Event::Cancel(reason())),
+        "auth_revoked" => Some(Event::AuthRevoked(reason())),
+        _ => None,
+    }
+}
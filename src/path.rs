@@ -0,0 +1,191 @@
+use std::fmt;
+
+use url::Url;
+use url::percent_encoding;
+
+/// A path to a location in the database, e.g. ```/shows/futurama/episodes```.
+///
+/// A ```DbPath``` is made of the raw, decoded keys of every location on the
+/// way down from the root. Percent-encoding only happens when the path is
+/// turned into a url, so paths can be compared and combined safely.
+///
+/// # Examples
+/// ```
+/// # use firebase::DbPath;
+/// let show = DbPath::new("/shows/futurama");
+/// let episode = show.join(&DbPath::new("episodes/140.json"));
+///
+/// assert_eq!(episode.to_string(), "/shows/futurama/episodes/140");
+/// assert!(show.is_ancestor_of(&episode));
+/// assert_eq!(episode.relative_to(&show), Some(DbPath::new("episodes/140")));
+/// assert_eq!(episode.parent(), Some(DbPath::new("/shows/futurama/episodes")));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DbPath {
+    segments: Vec<String>,
+}
+
+impl DbPath {
+    /// Parses a path, slashes and a ```.json``` extension are handled
+    /// the same way as in ```Firebase::at```. Every component is taken as a raw key.
+    pub fn new(path: &str) -> Self {
+        let path = path.trim_matches('/');
+        let path = path.trim_right_matches(".json");
+
+        DbPath {
+            // Empty components would only produce double slashes, so skip them.
+            segments: path.split("/")
+                          .filter(|c| !c.is_empty())
+                          .map(|c| c.to_string())
+                          .collect(),
+        }
+    }
+
+    /// The path of the root of the database, ```/```.
+    pub fn root() -> Self {
+        DbPath {
+            segments: Vec::new(),
+        }
+    }
+
+    /// Returns true if this is the root of the database.
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Returns the keys making up this path, starting at the root.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Returns the last key of this path, the root has no key.
+    pub fn key(&self) -> Option<&str> {
+        self.segments.last().map(|s| s as &str)
+    }
+
+    /// Creates a new path that points to ```other``` relative to this path.
+    pub fn join(&self, other: &DbPath) -> Self {
+        let mut segments = self.segments.clone();
+        segments.extend(other.segments.iter().cloned());
+        DbPath {
+            segments: segments,
+        }
+    }
+
+    /// Creates a new path pointing to the parent of this path,
+    /// the root has no parent so ```None``` is returned.
+    pub fn parent(&self) -> Option<Self> {
+        let mut segments = self.segments.clone();
+        match segments.pop() {
+            Some(_) => Some(DbPath { segments: segments }),
+            None    => None,
+        }
+    }
+
+    /// Returns true if ```other``` is somewhere below this path.
+    /// A path is not an ancestor of itself.
+    pub fn is_ancestor_of(&self, other: &DbPath) -> bool {
+        self.segments.len() < other.segments.len()
+            && other.segments.starts_with(&self.segments)
+    }
+
+    /// Returns the path of ```self``` relative to ```ancestor```, or ```None```
+    /// if ```self``` is not at or below ```ancestor```.
+    /// A path relative to itself is the root path.
+    pub fn relative_to(&self, ancestor: &DbPath) -> Option<Self> {
+        if self.segments.starts_with(&ancestor.segments) {
+            Some(DbPath {
+                segments: self.segments[ancestor.segments.len()..].to_vec(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> From<&'a str> for DbPath {
+    fn from(path: &'a str) -> Self {
+        DbPath::new(path)
+    }
+}
+
+impl fmt::Display for DbPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.segments.is_empty() {
+            return f.write_str("/");
+        }
+        for segment in self.segments.iter() {
+            try!(f.write_str("/"));
+            try!(f.write_str(segment));
+        }
+        Ok(())
+    }
+}
+
+/// Reads the path out of a url, undoing any percent-encoding.
+pub fn from_url(url: &Url) -> DbPath {
+    DbPath {
+        segments: url.path().unwrap_or(&[]).iter()
+                     .map(|s| s.trim_right_matches(".json"))
+                     .filter(|s| !s.is_empty())
+                     .map(|s| decode_segment(s))
+                     .collect(),
+    }
+}
+
+/// Creates a path of keys as they are, e.g. keys read from JSON.
+pub fn from_segments(segments: Vec<String>) -> DbPath {
+    DbPath {
+        segments: segments,
+    }
+}
+
+/// Splits a path into its keys as they are, e.g. the path of an event.
+/// Unlike ```DbPath::new``` a key may end in ```.json```.
+pub fn from_keys(path: &str) -> DbPath {
+    from_segments(path.split('/').filter(|k| !k.is_empty()).map(|k| k.to_string()).collect())
+}
+
+/// The url with its query parameters sorted, so the same query
+/// always makes the same key, e.g. in a ```Cache```.
+pub fn canonical(url: &Url) -> String {
+    let mut url = url.clone();
+    if let Some(mut pairs) = url.query_pairs() {
+        pairs.sort();
+        url.set_query_from_pairs(pairs.iter().map(|&(ref k, ref v)| (&k[..], &v[..])));
+    }
+    url.serialize()
+}
+
+/// Sets the path of a url to ```path```, every key is percent-encoded on
+/// its own and the ```.json``` extension is added to the last one, or
+/// makes up the whole path of the root, e.g. ```https://x.firebaseio.com/.json```.
+pub fn set_on_url(url: &mut Url, path: &DbPath) {
+    let mut encoded: Vec<String> = path.segments.iter()
+                                       .map(|s| encode_segment(s))
+                                       .collect();
+    let end = encoded.pop().unwrap_or(String::new());
+    encoded.push(end + ".json");
+    if let Some(url_path) = url.path_mut() {
+        *url_path = encoded;
+    }
+}
+
+/// Percent-encodes a single path segment. Only unreserved characters are left
+/// as they are, so reserved characters like ```/```, ```?``` and ```%``` can't
+/// change the meaning of the url.
+pub fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for &byte in segment.as_bytes() {
+        match byte {
+            b'A' ... b'Z' | b'a' ... b'z' | b'0' ... b'9' |
+            b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn decode_segment(segment: &str) -> String {
+    percent_encoding::lossy_utf8_percent_decode(segment.as_bytes())
+}
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+
+use rustc_serialize::json::Json;
+use url::Url;
+
+use path::{self, DbPath};
+use {Method, ReqErr, Response};
+
+/// Keeps writes that couldn't reach the server in a journal on disk,
+/// and replays them in order once it can be reached again.
+///
+/// A write made through a reference with the queue that fails because the
+/// server can't be reached, after any retries, is added to the journal and
+/// returns ```Err(ReqErr::Queued)```. Every later write replays the journal
+/// first, and is queued behind it if the server still can't be reached,
+/// so writes always arrive in the order they were made.
+/// ```Firebase::replay_writes``` replays the journal without a new write.
+///
+/// A replayed write the server refuses, e.g. because the rules changed in the
+/// meantime, is dropped from the journal and reported as a ```Conflict```.
+/// One refused with ```401 Unauthorized```, e.g. as a token expired while
+/// offline, stays in the journal to be replayed with a fresh token.
+///
+/// The journal keeps no tokens, replayed writes are authorized like the
+/// reference replaying them, so a queue should only be shared by references
+/// with the same credentials. ```Firebase::auth_with``` leaves it off the
+/// reference it creates. A write that reached the server right before the
+/// connection broke may be applied twice, which only matters for ```push```.
+/// Futures of the ```nonblocking``` feature don't use the queue.
+///
+/// # Examples
+/// ```no_run
+/// # use firebase::*;
+/// let queue = WriteQueue::open("/var/lib/sensor/writes.journal").unwrap().on_conflict(|conflict| {
+///     println!("{} refused with {}", conflict.write.path(), conflict.response.code)
+/// });
+/// let readings = Firebase::new("https://myfb.firebaseio.com/readings").unwrap()
+///                         .with_write_queue(queue);
+///
+/// match readings.push("{\"celsius\": 21.5}") {
+///     Err(ReqErr::Queued) => println!("Offline, sending it later"),
+///     other => println!("{:?}", other),
+/// }
+/// ```
+pub struct WriteQueue {
+    path:     PathBuf,
+    state:    Mutex<State>,
+    conflict: Option<Box<Fn(Conflict) + Send + Sync>>,
+}
+
+struct State {
+    writes:  VecDeque<QueuedWrite>,
+    journal: File,
+}
+
+/// A write waiting in a ```WriteQueue```.
+#[derive(Clone, Debug)]
+pub struct QueuedWrite {
+    method: Method,
+    url:    Url,
+    data:   Option<String>,
+}
+
+/// A queued write the server refused when it was replayed.
+#[derive(Debug)]
+pub struct Conflict {
+    pub write:    QueuedWrite,
+    pub response: Response,
+}
+
+impl WriteQueue {
+    /// Opens the journal at ```path``` with the writes left in it,
+    /// or creates an empty one.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut journal = try!( OpenOptions::new().create(true)
+                                                  .read(true)
+                                                  .append(true)
+                                                  .open(&path) );
+        let (writes, torn) = try!( read_journal(&mut journal) );
+        if torn {
+            journal = try!( rewrite(&path, &writes) );
+        }
+
+        Ok(WriteQueue {
+            path:     path,
+            state:    Mutex::new(State {
+                writes:  writes,
+                journal: journal,
+            }),
+            conflict: None,
+        })
+    }
+
+    /// Calls ```handler``` with every replayed write the server refuses.
+    pub fn on_conflict<F>(mut self, handler: F) -> Self
+    where F: Fn(Conflict) + Send + Sync + 'static {
+        self.conflict = Some(Box::new(handler));
+        self
+    }
+
+    /// The number of writes waiting to be replayed.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The writes waiting to be replayed, oldest first.
+    pub fn pending(&self) -> Vec<QueuedWrite> {
+        self.state.lock().unwrap().writes.iter().cloned().collect()
+    }
+}
+
+impl fmt::Debug for WriteQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteQueue")
+         .field("path", &self.path)
+         .field("len", &self.len())
+         .finish()
+    }
+}
+
+impl QueuedWrite {
+    /// The HTTP method of the write, e.g. ```PUT``` for a ```set```.
+    pub fn method(&self) -> &'static str {
+        method_name(self.method)
+    }
+
+    pub fn path(&self) -> DbPath {
+        path::from_url(&self.url)
+    }
+
+    /// The JSON that was written, if any.
+    pub fn data(&self) -> Option<&str> {
+        self.data.as_ref().map(|d| &d[..])
+    }
+}
+
+/// Makes a write with ```send```, after replaying the writes queued before it.
+/// Queues it if the server can't be reached, also to fetch a token.
+pub fn write<S>(queue: &WriteQueue, url: &Url, method: Method, data: Option<&str>,
+                send: S) -> Result<Response, ReqErr>
+where S: Fn(&Url, Method, Option<&str>) -> Result<Response, ReqErr> {
+    let write = QueuedWrite {
+        method: method,
+        url:    url.clone(),
+        data:   data.map(|d| d.to_string()),
+    };
+
+    // The lock is held while sending, so concurrent writes keep their order.
+    let (result, conflicts) = {
+        let mut state = queue.state.lock().unwrap();
+        let (replayed, conflicts) = replay_locked(queue, &mut state, &send);
+        let result = if !replayed {
+            enqueue(&mut state, write)
+        } else {
+            match send(url, method, data) {
+                Err(ref e) if e.is_offline() => enqueue(&mut state, write),
+                result => result,
+            }
+        };
+        (result, conflicts)
+    };
+    report(queue, conflicts);
+    result
+}
+
+/// Replays the queued writes with ```send```, returning how many are still waiting.
+pub fn replay<S>(queue: &WriteQueue, send: S) -> usize
+where S: Fn(&Url, Method, Option<&str>) -> Result<Response, ReqErr> {
+    let (left, conflicts) = {
+        let mut state = queue.state.lock().unwrap();
+        let (_, conflicts) = replay_locked(queue, &mut state, &send);
+        (state.writes.len(), conflicts)
+    };
+    report(queue, conflicts);
+    left
+}
+
+/// Sends the queued writes in order, until one can't be delivered. Returns
+/// whether all of them were, and the ones the server refused.
+fn replay_locked<S>(queue: &WriteQueue, state: &mut State, send: &S) -> (bool, Vec<Conflict>)
+where S: Fn(&Url, Method, Option<&str>) -> Result<Response, ReqErr> {
+    let mut conflicts = Vec::new();
+    let mut delivered = 0;
+    let done = loop {
+        let result = match state.writes.front() {
+            Some(write) => send(&write.url, write.method, write.data()),
+            None        => break true,
+        };
+        match result {
+            Ok(ref res) if res.code < 300 => { state.writes.pop_front(); },
+            Ok(res) => {
+                if !is_refusal(res.code) {
+                    break false;
+                }
+                let write = state.writes.pop_front().unwrap();
+                conflicts.push(Conflict { write: write, response: res });
+            },
+            Err(_) => break false,
+        }
+        delivered += 1;
+    };
+
+    if delivered > 0 {
+        // If this fails the old journal is still in place, and the
+        // delivered writes would only be sent again after a restart.
+        if let Ok(journal) = rewrite(&queue.path, &state.writes) {
+            state.journal = journal;
+        }
+    }
+    (done, conflicts)
+}
+
+/// Adds ```write``` to the end of the journal.
+fn enqueue(state: &mut State, write: QueuedWrite) -> Result<Response, ReqErr> {
+    let record = encode(&write) + "\n";
+    try!( state.journal.write_all(record.as_bytes())
+                       .and_then(|_| state.journal.sync_data())
+                       .map_err(ReqErr::QueueErr) );
+    state.writes.push_back(write);
+    Err(ReqErr::Queued)
+}
+
+fn report(queue: &WriteQueue, conflicts: Vec<Conflict>) {
+    if let Some(ref handler) = queue.conflict {
+        for conflict in conflicts {
+            handler(conflict);
+        }
+    }
+}
+
+/// Whether the server refused a write for good, rather than for now.
+fn is_refusal(code: u32) -> bool {
+    match code {
+        401 | 408 | 429 => false,
+        400 ... 499     => true,
+        _               => false,
+    }
+}
+
+/// Reads the writes in a journal, and whether its last record was cut off.
+fn read_journal(journal: &mut File) -> io::Result<(VecDeque<QueuedWrite>, bool)> {
+    let mut bytes = Vec::new();
+    try!( journal.read_to_end(&mut bytes) );
+
+    let records: Vec<&[u8]> = bytes.split(|&b| b == b'\n').filter(|r| !r.is_empty()).collect();
+    let mut writes = VecDeque::new();
+    for (i, record) in records.iter().enumerate() {
+        match str::from_utf8(record).ok().and_then(decode) {
+            Some(write) => writes.push_back(write),
+            // Dying while appending leaves the last record cut off.
+            None if i + 1 == records.len() => return Ok((writes, true)),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "write journal is corrupt")),
+        }
+    }
+    Ok((writes, false))
+}
+
+/// Replaces the journal with one holding ```writes```, in a way
+/// that leaves either the old or the new one after a crash.
+fn rewrite(path: &Path, writes: &VecDeque<QueuedWrite>) -> io::Result<File> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    {
+        let mut file = try!( File::create(&temp) );
+        for write in writes {
+            try!( file.write_all((encode(write) + "\n").as_bytes()) );
+        }
+        try!( file.sync_all() );
+    }
+    try!( fs::rename(&temp, path) );
+    OpenOptions::new().read(true).append(true).open(path)
+}
+
+fn encode(write: &QueuedWrite) -> String {
+    let mut record = BTreeMap::new();
+    record.insert("method".to_string(), Json::String(method_name(write.method).to_string()));
+    record.insert("url".to_string(), Json::String(write.url.serialize()));
+    record.insert("data".to_string(), write.data.clone().map(Json::String).unwrap_or(Json::Null));
+    Json::Object(record).to_string()
+}
+
+fn decode(record: &str) -> Option<QueuedWrite> {
+    let record = match Json::from_str(record) {
+        Ok(Json::Object(record)) => record,
+        _ => return None,
+    };
+    let method = match record.get("method").and_then(|m| m.as_string()) {
+        Some("POST")   => Method::POST,
+        Some("PUT")    => Method::PUT,
+        Some("PATCH")  => Method::PATCH,
+        Some("DELETE") => Method::DELETE,
+        _ => return None,
+    };
+    let url = match record.get("url").and_then(|u| u.as_string()).and_then(|u| Url::parse(u).ok()) {
+        Some(url) => url,
+        None      => return None,
+    };
+    let data = match record.get("data") {
+        Some(&Json::String(ref data)) => Some(data.clone()),
+        Some(&Json::Null)             => None,
+        _ => return None,
+    };
+
+    Some(QueuedWrite {
+        method: method,
+        url:    url,
+        data:   data,
+    })
+}
+
+fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::GET    => "GET",
+        Method::POST   => "POST",
+        Method::PUT    => "PUT",
+        Method::PATCH  => "PATCH",
+        Method::DELETE => "DELETE",
+    }
+}
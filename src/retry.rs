@@ -0,0 +1,171 @@
+use std::cmp;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use {Method, ReqErr, Response};
+
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(v) => v, None => return None })
+}
+
+const DEFAULT_RETRIES:   u32 = 3;
+const DEFAULT_INITIAL:   u64 = 250;
+const DEFAULT_MAX:       u64 = 30 * 1000;
+const TOO_MANY_REQUESTS: u32 = 429;
+
+/// Tells a ```Firebase``` reference when to try a failed request again.
+///
+/// Requests that fail with a network error, a timeout, a 5xx status or
+/// ```429 Too Many Requests``` are retried after an exponentially growing,
+/// randomly jittered delay, or after the delay asked for in a ```Retry-After```
+/// header if that is longer.
+///
+/// Only idempotent requests are retried: GET, PUT and DELETE, and PATCH when
+/// ```retry_patch``` is set. A POST (```push```) is never retried, as a request
+/// that failed on the way back would create a second child.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::new()
+///     .max_retries(5)
+///     .backoff(Duration::from_millis(100), Duration::from_secs(10));
+/// let fb = Firebase::new("https://myfb.firebaseio.com").unwrap().retry_policy(policy);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_retries:     u32,
+    initial_backoff: Duration,
+    max_backoff:     Duration,
+    retry_patch:     bool,
+}
+
+impl RetryPolicy {
+    /// Creates a policy that retries up to 3 times, waiting 250 milliseconds
+    /// at first and at most 30 seconds between attempts.
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_retries:     DEFAULT_RETRIES,
+            initial_backoff: Duration::from_millis(DEFAULT_INITIAL),
+            max_backoff:     Duration::from_millis(DEFAULT_MAX),
+            retry_patch:     false,
+        }
+    }
+
+    /// Sets how many times a request is tried again after the first attempt.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, which doubles on every
+    /// retry after that until it reaches ```max```.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Allows PATCH (```update```) requests to be retried. Only turn this on
+    /// if applying the same update twice is harmless for your data.
+    pub fn retry_patch(mut self, retry: bool) -> Self {
+        self.retry_patch = retry;
+        self
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+/// Returns how long to wait before trying the request again, or ```None```
+/// if ```result``` is final. ```retries``` is the number of retries made so far.
+pub fn next_delay(policy: &RetryPolicy, method: &Method, retries: u32,
+                  result: &Result<Response, ReqErr>, retry_after: Option<Duration>) -> Option<Duration> {
+    let idempotent = match *method {
+        Method::GET | Method::PUT | Method::DELETE => true,
+        Method::PATCH => policy.retry_patch,
+        Method::POST  => false,
+    };
+    let failed = match *result {
+        Ok(ref res) => res.code >= 500 || res.code == TOO_MANY_REQUESTS,
+        Err(ReqErr::NetworkErr(_)) | Err(ReqErr::TransportErr(_)) | Err(ReqErr::Timeout) => true,
+        Err(_) => false,
+    };
+    if !idempotent || !failed || retries >= policy.max_retries {
+        return None;
+    }
+
+    let delay = jitter(backoff(policy, retries));
+    Some(match retry_after {
+        Some(after) => cmp::max(delay, after),
+        None        => delay,
+    })
+}
+
+/// The delay before retry number ```retries``` without any jitter.
+fn backoff(policy: &RetryPolicy, retries: u32) -> Duration {
+    let factor = 1u32.checked_shl(retries).unwrap_or(u32::max_value());
+    policy.initial_backoff.checked_mul(factor)
+          .map(|delay| cmp::min(delay, policy.max_backoff))
+          .unwrap_or(policy.max_backoff)
+}
+
+/// Picks a random delay between half of ```delay``` and all of it, so clients
+/// that failed together don't all come back at the same time.
+fn jitter(delay: Duration) -> Duration {
+    let mut bytes = [0u8; 4];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return delay;
+    }
+    let random = bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+    let half = delay / 2;
+    let nanos = half.as_secs() * 1_000_000_000 + half.subsec_nanos() as u64;
+    let extra = nanos as f64 * (random as f64 / u32::max_value() as f64);
+    half + Duration::from_nanos(extra as u64)
+}
+
+/// Parses a ```Retry-After``` header, either a number of seconds
+/// or an HTTP date like ```Sun, 06 Nov 1994 08:49:37 GMT```.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = try_opt!(parse_http_date(value));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    Some(Duration::from_secs(at.saturating_sub(now)))
+}
+
+/// Parses an IMF-fixdate into seconds since the unix epoch.
+fn parse_http_date(date: &str) -> Option<u64> {
+    const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let parts: Vec<&str> = date.split(' ').collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+    let day: u64 = try_opt!(parts[1].parse().ok());
+    let month = try_opt!(MONTHS.iter().position(|&m| m == parts[2])) as u64 + 1;
+    let year: u64 = try_opt!(parts[3].parse().ok());
+    let time: Vec<u64> = parts[4].split(':').filter_map(|p| p.parse().ok()).collect();
+    if time.len() != 3 || year < 1970 {
+        return None;
+    }
+
+    // Days since the epoch of a date in the proleptic gregorian calendar.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
@@ -0,0 +1,249 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, DecoderError, Json};
+use url::Url;
+
+use path::{self, DbPath};
+use {ReqErr, Response};
+
+/// Marks the priority of a value in ```format=export``` data.
+const PRIORITY: &'static str = ".priority";
+/// Holds a value that has a priority but no children.
+const VALUE:    &'static str = ".value";
+
+/// The data at a location, with its children in the order of the query
+/// that read it. The REST API leaves the order of an ```order_by``` query to
+/// the client, so the children are sorted the way Firebase sorts them.
+///
+/// Priorities are only sent by the server for reads with ```format()```.
+///
+/// # Examples
+/// ```no_run
+/// # use firebase::*;
+/// let dinosaurs = Firebase::new("https://dinosaur-facts.firebaseio.com/dinosaurs").unwrap();
+/// let tallest = dinosaurs.order_by("\"height\"").limit_to_last(3).get_snapshot().unwrap();
+///
+/// for dinosaur in tallest.children() {
+///     let height: f64 = dinosaur.child("height").val().unwrap();
+///     println!("{} is {}m tall", dinosaur.key().unwrap(), height);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSnapshot {
+    path:  DbPath,
+    data:  Json,
+    order: Order,
+}
+
+/// How the children of a snapshot are sorted, from the ```orderBy``` of its query.
+#[derive(Clone, Debug, PartialEq)]
+enum Order {
+    Key,
+    Value,
+    Priority,
+    Child(DbPath),
+}
+
+impl DataSnapshot {
+    /// Wraps ```data``` read at ```path```, e.g. from a listener,
+    /// with its children sorted by key.
+    pub fn new<P: Into<DbPath>>(path: P, data: Json) -> Self {
+        DataSnapshot {
+            path:  path.into(),
+            data:  data,
+            order: Order::Key,
+        }
+    }
+
+    /// The last key of the path, ```None``` for the root.
+    pub fn key(&self) -> Option<&str> {
+        self.path.key()
+    }
+
+    pub fn path(&self) -> &DbPath {
+        &self.path
+    }
+
+    /// The data at ```path``` below this one, which
+    /// doesn't exist if there is nothing there.
+    ///
+    /// The keys of ```path``` are taken as they are, like the keys of
+    /// ```children```, so unlike in ```Firebase::at``` a ```.json``` ending
+    /// is part of the last key.
+    pub fn child(&self, path: &str) -> DataSnapshot {
+        let path = path::from_keys(path);
+        let mut node = &self.data;
+        for key in path.segments() {
+            node = child(node, key).unwrap_or(&Json::Null);
+        }
+        DataSnapshot::new(self.path.join(&path), node.clone())
+    }
+
+    pub fn exists(&self) -> bool {
+        !value(&self.data).is_null()
+    }
+
+    pub fn has_child(&self, path: &str) -> bool {
+        self.child(path).exists()
+    }
+
+    pub fn has_children(&self) -> bool {
+        self.num_children() > 0
+    }
+
+    pub fn num_children(&self) -> usize {
+        entries(&self.data).len()
+    }
+
+    /// The children, sorted by the ```order_by``` of the query, or by key.
+    /// Ties are sorted by key as well.
+    pub fn children(&self) -> Vec<DataSnapshot> {
+        let mut entries = entries(&self.data);
+        entries.sort_by(|&(ref a, a_data), &(ref b, b_data)| {
+            let by_order = match self.order {
+                Order::Key             => Ordering::Equal,
+                Order::Value           => compare_values(value(a_data), value(b_data)),
+                Order::Priority        => compare_values(priority(a_data), priority(b_data)),
+                Order::Child(ref path) => compare_values(value(descendant(a_data, path)),
+                                                         value(descendant(b_data, path))),
+            };
+            by_order.then_with(|| compare_keys(a, b))
+        });
+        entries.into_iter()
+               .map(|(key, data)| DataSnapshot::new(self.path.join(&path::from_segments(vec![key])), data.clone()))
+               .collect()
+    }
+
+    /// The priority of the data, if it has one.
+    pub fn priority(&self) -> Option<&Json> {
+        match *priority(&self.data) {
+            Json::Null => None,
+            ref p      => Some(p),
+        }
+    }
+
+    /// The data, without priorities.
+    pub fn json(&self) -> Json {
+        without_priorities(&self.data)
+    }
+
+    /// Decodes the data, without priorities, into a ```T```.
+    pub fn val<T: Decodable>(&self) -> Result<T, DecoderError> {
+        T::decode(&mut json::Decoder::new(self.json()))
+    }
+}
+
+/// Makes a snapshot of a successful read of ```url```, whose
+/// children are sorted by the ```orderBy``` parameter, if any.
+pub fn from_response(url: &Url, res: Response, order_by: Option<&str>) -> Result<DataSnapshot, ReqErr> {
+    if res.code != 200 {
+        return Err(ReqErr::Refused(res));
+    }
+    let data = try!( res.json().map_err(ReqErr::RespNotJSON) );
+
+    let order = match order_by.map(|o| o.trim_matches('"')) {
+        None | Some("$key") => Order::Key,
+        Some("$value")      => Order::Value,
+        Some("$priority")   => Order::Priority,
+        Some(child)         => Order::Child(DbPath::new(child)),
+    };
+    Ok(DataSnapshot {
+        path:  path::from_url(url),
+        data:  data,
+        order: order,
+    })
+}
+
+/// The value of exported data that has a priority but no children.
+fn value(data: &Json) -> &Json {
+    match data.find(VALUE) {
+        Some(value) => value,
+        None        => data,
+    }
+}
+
+fn priority(data: &Json) -> &Json {
+    data.find(PRIORITY).unwrap_or(&Json::Null)
+}
+
+/// The child at ```key```, arrays are keyed by index.
+fn child<'d>(data: &'d Json, key: &str) -> Option<&'d Json> {
+    match *value(data) {
+        Json::Object(ref children) if key != PRIORITY => children.get(key),
+        Json::Array(ref items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    }
+}
+
+fn descendant<'d>(data: &'d Json, path: &DbPath) -> &'d Json {
+    path.segments().iter().fold(data, |node, key| child(node, key).unwrap_or(&Json::Null))
+}
+
+/// The children that exist, with their keys.
+fn entries(data: &Json) -> Vec<(String, &Json)> {
+    match *value(data) {
+        Json::Object(ref children) => children.iter()
+            .filter(|&(key, child)| key != PRIORITY && !child.is_null())
+            .map(|(key, child)| (key.clone(), child))
+            .collect(),
+        Json::Array(ref items) => items.iter().enumerate()
+            .filter(|&(_, item)| !item.is_null())
+            .map(|(i, item)| (i.to_string(), item))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn without_priorities(data: &Json) -> Json {
+    match *value(data) {
+        Json::Object(ref children) => {
+            let children: BTreeMap<String, Json> = children.iter()
+                .filter(|&(key, _)| key != PRIORITY)
+                .map(|(key, child)| (key.clone(), without_priorities(child)))
+                .collect();
+            Json::Object(children)
+        },
+        Json::Array(ref items) => Json::Array(items.iter().map(without_priorities).collect()),
+        ref leaf => leaf.clone(),
+    }
+}
+
+/// Sorts keys like Firebase: keys that are 32 bit integers
+/// come first and are sorted as numbers, then the others.
+fn compare_keys(a: &str, b: &str) -> Ordering {
+    match (int_key(a), int_key(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None)    => Ordering::Less,
+        (None, Some(_))    => Ordering::Greater,
+        (None, None)       => a.cmp(b),
+    }
+}
+
+fn int_key(key: &str) -> Option<i32> {
+    key.parse::<i32>().ok().and_then(|n| if n.to_string() == key { Some(n) } else { None })
+}
+
+/// Sorts values like Firebase: null, false, true, numbers,
+/// strings and then objects, which are all equal.
+fn compare_values(a: &Json, b: &Json) -> Ordering {
+    match (a, b) {
+        (&Json::String(ref a), &Json::String(ref b)) => a.cmp(b),
+        _ => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _                  => rank(a).cmp(&rank(b)),
+        },
+    }
+}
+
+fn rank(value: &Json) -> u8 {
+    match *value {
+        Json::Null                                  => 0,
+        Json::Boolean(false)                        => 1,
+        Json::Boolean(true)                         => 2,
+        Json::I64(_) | Json::U64(_) | Json::F64(_)  => 3,
+        Json::String(_)                             => 4,
+        Json::Array(_) | Json::Object(_)            => 5,
+    }
+}
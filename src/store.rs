@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+
+use rustc_serialize::json::Json;
+use url::Url;
+
+use path;
+#[cfg(feature = "nonblocking")]
+use path::DbPath;
+use {ReqErr, Response};
+
+/// A log is compacted once it holds more records than this,
+/// and more than twice as many as there are urls.
+const COMPACT_AFTER: usize = 64;
+
+/// Keeps the last known data of read and listened locations in a file,
+/// to answer reads with it while the server can't be reached.
+///
+/// Every successful get through a reference with the store saves its result,
+/// per url like a ```Cache```, and listeners of the ```nonblocking``` feature
+/// keep the data of their url up to date as events come in. A get that fails
+/// because the server can't be reached, also to fetch a token, returns the
+/// saved data instead, as a response with ```stale``` set. Other errors, like
+/// a refused token, are returned as they are.
+///
+/// Every save appends the new data of its url to the file, which is rewritten
+/// once most of it is data that was replaced since.
+///
+/// The store holds the data of every url it saw, until it is cleared. Like a
+/// cache, it should only be shared by references with the same credentials,
+/// and ```Firebase::auth_with``` leaves it off the reference it creates.
+///
+/// # Examples
+/// ```no_run
+/// # use firebase::*;
+/// let store = SnapshotStore::open("/var/lib/app/snapshots.log").unwrap();
+/// let settings = Firebase::new("https://myfb.firebaseio.com/settings").unwrap().with_snapshot_store(store);
+///
+/// let res = settings.get().unwrap();
+/// if res.stale {
+///     println!("Offline, showing the last known settings");
+/// }
+/// ```
+pub struct SnapshotStore {
+    path:  PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    snapshots: HashMap<String, String>,
+    /// Every save is appended to the log, the latest one of a url wins.
+    log:       File,
+    /// The number of records in the log, including replaced ones.
+    records:   usize,
+}
+
+impl SnapshotStore {
+    /// Opens the store saved at ```path```, or creates an empty one.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut log = try!( OpenOptions::new().create(true).read(true).append(true).open(&path) );
+        let (snapshots, records, torn) = try!( read_log(&mut log) );
+        let mut state = State {
+            snapshots: snapshots,
+            log:       log,
+            records:   records,
+        };
+        if torn {
+            try!( compact(&path, &mut state) );
+        }
+
+        Ok(SnapshotStore {
+            path:  path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Drops the data of every url, also from the file.
+    pub fn clear(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.snapshots.clear();
+        compact(&self.path, &mut state)
+    }
+
+    /// The number of urls with saved data.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for SnapshotStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnapshotStore")
+         .field("path", &self.path)
+         .field("len", &self.len())
+         .finish()
+    }
+}
+
+/// Saves the result of a successful read of ```url```, or answers a
+/// failed one with the saved data.
+pub fn read_through(store: &SnapshotStore, url: &Url, result: Result<Response, ReqErr>) -> Result<Response, ReqErr> {
+    match result {
+        Ok(res) => {
+            if res.code == 200 && !res.stale {
+                // A failure to save only means the data can't be read offline.
+                let _ = update(store, url, |_| Some(res.body.clone()));
+            }
+            Ok(res)
+        },
+        Err(e) => match store.state.lock().unwrap().snapshots.get(&path::canonical(url)) {
+            Some(body) if e.is_offline() => Ok(Response { body: body.clone(), code: 200, stale: true }),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Applies an event of a listener of ```url``` to its saved data. ```data```
+/// replaces the data at ```path```, or is merged into it for a ```patch```.
+#[cfg(feature = "nonblocking")]
+pub fn apply(store: &SnapshotStore, url: &Url, path: &DbPath, data: &Json, patch: bool) -> io::Result<()> {
+    update(store, url, |saved| {
+        let mut root = if path.is_root() && !patch {
+            Json::Null
+        } else {
+            saved.and_then(|saved| Json::from_str(saved).ok()).unwrap_or(Json::Null)
+        };
+        match (patch, data) {
+            (true, &Json::Object(ref children)) => {
+                for (key, child) in children {
+                    let child_path = path.join(&path::from_keys(key));
+                    set(&mut root, child_path.segments(), child.clone());
+                }
+            },
+            _ => set(&mut root, path.segments(), data.clone()),
+        }
+        Some(root.to_string())
+    })
+}
+
+/// Replaces the data saved for ```url``` with what ```f``` makes of it,
+/// appending it to the log if it changed.
+fn update<F>(store: &SnapshotStore, url: &Url, f: F) -> io::Result<()>
+where F: FnOnce(Option<&String>) -> Option<String> {
+    let key = path::canonical(url);
+    let mut state = store.state.lock().unwrap();
+    let body = match f(state.snapshots.get(&key)) {
+        Some(body) => body,
+        None       => return Ok(()),
+    };
+    if state.snapshots.get(&key) == Some(&body) {
+        return Ok(());
+    }
+
+    let record = encode(&key, &body) + "\n";
+    state.snapshots.insert(key, body);
+    try!( state.log.write_all(record.as_bytes()) );
+    state.records += 1;
+    if state.records > COMPACT_AFTER && state.records > 2 * state.snapshots.len() {
+        try!( compact(&store.path, &mut state) );
+    }
+    Ok(())
+}
+
+/// Sets the value at ```segments``` below ```node```, creating the objects on
+/// the way. Null removes the value, and the objects it leaves empty.
+#[cfg(feature = "nonblocking")]
+fn set(node: &mut Json, segments: &[String], value: Json) {
+    if segments.is_empty() {
+        *node = value;
+        return;
+    }
+    if !node.is_object() {
+        if value.is_null() {
+            return;
+        }
+        *node = Json::Object(BTreeMap::new());
+    }
+    if let Json::Object(ref mut children) = *node {
+        {
+            let child = children.entry(segments[0].clone()).or_insert(Json::Null);
+            set(child, &segments[1..], value);
+        }
+        if children.get(&segments[0]).map_or(false, |child| child.is_null()) {
+            children.remove(&segments[0]);
+        }
+    }
+    if node.as_object().map_or(false, |children| children.is_empty()) {
+        *node = Json::Null;
+    }
+}
+
+/// Reads the saved data in a log, how many records it holds,
+/// and whether its last record was cut off.
+fn read_log(log: &mut File) -> io::Result<(HashMap<String, String>, usize, bool)> {
+    let mut bytes = Vec::new();
+    try!( log.read_to_end(&mut bytes) );
+
+    let records: Vec<&[u8]> = bytes.split(|&b| b == b'\n').filter(|r| !r.is_empty()).collect();
+    let mut snapshots = HashMap::new();
+    for (i, record) in records.iter().enumerate() {
+        match str::from_utf8(record).ok().and_then(decode) {
+            Some((url, body)) => { snapshots.insert(url, body); },
+            // Dying while appending leaves the last record cut off.
+            None if i + 1 == records.len() => return Ok((snapshots, i, true)),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot store is corrupt")),
+        }
+    }
+    Ok((snapshots, records.len(), false))
+}
+
+/// Replaces the log with one holding only the latest data of every url,
+/// in a way that leaves either the old or the new one after a crash.
+fn compact(path: &Path, state: &mut State) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    {
+        let mut file = try!( File::create(&temp) );
+        for (url, body) in &state.snapshots {
+            try!( file.write_all((encode(url, body) + "\n").as_bytes()) );
+        }
+        try!( file.sync_all() );
+    }
+    try!( fs::rename(&temp, path) );
+    state.log = try!( OpenOptions::new().read(true).append(true).open(path) );
+    state.records = state.snapshots.len();
+    Ok(())
+}
+
+fn encode(url: &str, body: &str) -> String {
+    let mut record = BTreeMap::new();
+    record.insert("url".to_string(), Json::String(url.to_string()));
+    record.insert("body".to_string(), Json::String(body.to_string()));
+    Json::Object(record).to_string()
+}
+
+/// Decodes a record, with its url in the canonical form
+/// even if it was saved before urls were sorted.
+fn decode(record: &str) -> Option<(String, String)> {
+    let record = match Json::from_str(record) {
+        Ok(Json::Object(record)) => record,
+        _ => return None,
+    };
+    let url = match record.get("url").and_then(|u| u.as_string()).and_then(|u| Url::parse(u).ok()) {
+        Some(url) => path::canonical(&url),
+        None      => return None,
+    };
+    match record.get("body") {
+        Some(&Json::String(ref body)) => Some((url, body.clone())),
+        _ => None,
+    }
+}
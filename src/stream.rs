@@ -0,0 +1,167 @@
+use std::io::{self, Read};
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use rustc_serialize::json::{Json, JsonEvent, Parser, ParserError, StackElement};
+
+use path::{self, DbPath};
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Something found by a ```StreamParser```, with its path from the root of the document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseEvent {
+    /// An object starts. Arrays are treated as objects keyed by index,
+    /// like Firebase stores them.
+    Start(DbPath),
+    /// The object started at the same path ends.
+    End(DbPath),
+    /// A value that is not an object: a string, number, boolean or null.
+    Leaf(DbPath, Json),
+}
+
+/// Parses JSON from a reader as it is read, without building a ```Json``` tree,
+/// so exports of any size can be filtered, transformed or counted with constant
+/// memory. Only the keys of the objects around the current value are kept.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// let export = r#"{"users": {"fry": {"age": 1025}, "leela": {"age": 25}}}"#;
+///
+/// let ages: Vec<(String, Json)> = StreamParser::new(export.as_bytes())
+///     .filter_map(|event| match event {
+///         Ok(ParseEvent::Leaf(path, age)) => Some((path.parent().unwrap().key().unwrap().to_string(), age)),
+///         _ => None,
+///     })
+///     .collect();
+/// assert_eq!(ages, vec![("fry".to_string(), Json::U64(1025)), ("leela".to_string(), Json::U64(25))]);
+/// ```
+pub struct StreamParser<R> {
+    parser: Parser<Chars<R>>,
+    error:  Arc<Mutex<Option<io::Error>>>,
+    done:   bool,
+}
+
+impl<R: Read> StreamParser<R> {
+    pub fn new(reader: R) -> Self {
+        let error = Arc::new(Mutex::new(None));
+        StreamParser {
+            parser: Parser::new(Chars {
+                reader: reader,
+                buf:    vec![0; BUFFER_SIZE],
+                pos:    0,
+                len:    0,
+                error:  error.clone(),
+            }),
+            error: error,
+            done:  false,
+        }
+    }
+
+    /// The path of the value the parser is at.
+    fn path(&self) -> DbPath {
+        let stack = self.parser.stack();
+        path::from_segments((0..stack.len()).map(|i| match stack.get(i) {
+            StackElement::Key(key)     => key.to_string(),
+            StackElement::Index(index) => index.to_string(),
+        }).collect())
+    }
+}
+
+impl<R: Read> Iterator for StreamParser<R> {
+    type Item = Result<ParseEvent, ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = match self.parser.next() {
+            Some(event) => event,
+            None        => return None,
+        };
+
+        let leaf = |value| Some(Ok(ParseEvent::Leaf(self.path(), value)));
+        match event {
+            JsonEvent::ObjectStart | JsonEvent::ArrayStart => Some(Ok(ParseEvent::Start(self.path()))),
+            JsonEvent::ObjectEnd | JsonEvent::ArrayEnd     => Some(Ok(ParseEvent::End(self.path()))),
+            JsonEvent::BooleanValue(b) => leaf(Json::Boolean(b)),
+            JsonEvent::I64Value(n)     => leaf(Json::I64(n)),
+            JsonEvent::U64Value(n)     => leaf(Json::U64(n)),
+            JsonEvent::F64Value(n)     => leaf(Json::F64(n)),
+            JsonEvent::StringValue(s)  => leaf(Json::String(s)),
+            JsonEvent::NullValue       => leaf(Json::Null),
+            JsonEvent::Error(e) => {
+                self.done = true;
+                // A failed read looks like the end of the input to the parser.
+                match self.error.lock().unwrap().take() {
+                    Some(io_error) => Some(Err(ParserError::IoError(io_error))),
+                    None           => Some(Err(e)),
+                }
+            },
+        }
+    }
+}
+
+/// Decodes the UTF-8 of a reader into chars. Errors end the chars
+/// and are kept for the ```StreamParser``` to report.
+struct Chars<R> {
+    reader: R,
+    buf:    Vec<u8>,
+    pos:    usize,
+    len:    usize,
+    error:  Arc<Mutex<Option<io::Error>>>,
+}
+
+impl<R: Read> Chars<R> {
+    fn next_byte(&mut self) -> Option<u8> {
+        while self.pos == self.len {
+            match self.reader.read(&mut self.buf) {
+                Ok(0)  => return None,
+                Ok(n)  => { self.pos = 0; self.len = n; },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => {
+                    *self.error.lock().unwrap() = Some(e);
+                    return None;
+                },
+            }
+        }
+        self.pos += 1;
+        Some(self.buf[self.pos - 1])
+    }
+
+    fn fail(&mut self, msg: &'static str) -> Option<char> {
+        *self.error.lock().unwrap() = Some(io::Error::new(io::ErrorKind::InvalidData, msg));
+        None
+    }
+}
+
+impl<R: Read> Iterator for Chars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let first = match self.next_byte() {
+            Some(b) => b,
+            None    => return None,
+        };
+        let width = match first {
+            0x00 ... 0x7F => return Some(first as char),
+            0xC0 ... 0xDF => 2,
+            0xE0 ... 0xEF => 3,
+            0xF0 ... 0xF7 => 4,
+            _ => return self.fail("stream did not contain valid UTF-8"),
+        };
+
+        let mut bytes = [first, 0, 0, 0];
+        for i in 1..width {
+            match self.next_byte() {
+                Some(b) => bytes[i] = b,
+                None    => return self.fail("stream ended inside a UTF-8 character"),
+            }
+        }
+        match str::from_utf8(&bytes[..width]) {
+            Ok(s)  => s.chars().next(),
+            Err(_) => self.fail("stream did not contain valid UTF-8"),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use auth::AuthError;
+
+/// Tokens are refreshed this many seconds before they actually expire.
+pub const REFRESH_MARGIN: u64 = 300;
+
+/// A credential that is attached to a request, telling where it goes.
+/// The token is left out of the ```Debug``` output.
+#[derive(Clone, PartialEq)]
+pub enum AuthToken {
+    /// A database secret or a Firebase ID token, sent as the ```auth``` query parameter.
+    Auth(String),
+    /// An OAuth2 access token, sent as the ```access_token``` query parameter.
+    AccessToken(String),
+    /// An OAuth2 access token, sent as an ```Authorization: Bearer``` header.
+    Bearer(String),
+}
+
+impl AuthToken {
+    /// The token itself, wherever it is sent.
+    pub fn value(&self) -> &str {
+        match *self {
+            AuthToken::Auth(ref t)        => t,
+            AuthToken::AccessToken(ref t) => t,
+            AuthToken::Bearer(ref t)      => t,
+        }
+    }
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthToken::Auth(_)        => f.write_str("Auth(<redacted>)"),
+            AuthToken::AccessToken(_) => f.write_str("AccessToken(<redacted>)"),
+            AuthToken::Bearer(_)      => f.write_str("Bearer(<redacted>)"),
+        }
+    }
+}
+
+/// A token that stops being valid at ```expires_at```.
+#[derive(Clone, Debug)]
+pub struct ExpiringToken {
+    pub token: AuthToken,
+    pub expires_at: SystemTime,
+}
+
+impl ExpiringToken {
+    /// Returns true if the token has expired or will within ```margin```.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        match self.expires_at.duration_since(SystemTime::now()) {
+            Ok(left) => left <= margin,
+            Err(_)   => true,
+        }
+    }
+}
+
+/// Hands out the credential used to authenticate a request.
+///
+/// A ```Firebase``` instance asks its provider for a token every time it makes
+/// a request, so a provider can refresh or swap its token whenever it needs to
+/// without creating a new ```Firebase```.
+///
+/// Providers are implemented for an ```AuthToken```, which never changes,
+/// for ```CachedToken```, for ```ServiceAccountCredentials```, for any closure
+/// returning a ```Result<AuthToken, AuthError>``` and for an ```Arc``` of any of those.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// use std::sync::{Arc, RwLock};
+///
+/// let current = Arc::new(RwLock::new("first-id-token".to_string()));
+/// let latest = current.clone();
+/// let fb = Firebase::with_token_provider("https://myfb.firebaseio.com", move || {
+///     Ok(AuthToken::Auth(latest.read().unwrap().clone()))
+/// }).unwrap();
+///
+/// // Every request made from now on uses the new token.
+/// *current.write().unwrap() = "second-id-token".to_string();
+/// ```
+pub trait TokenProvider: Send + Sync {
+    /// Returns the token to attach to the next request.
+    fn token(&self) -> Result<AuthToken, AuthError>;
+}
+
+impl TokenProvider for AuthToken {
+    fn token(&self) -> Result<AuthToken, AuthError> {
+        Ok(self.clone())
+    }
+}
+
+impl<P> TokenProvider for Arc<P> where P: TokenProvider + ?Sized {
+    fn token(&self) -> Result<AuthToken, AuthError> {
+        (**self).token()
+    }
+}
+
+impl<F> TokenProvider for F where F: Fn() -> Result<AuthToken, AuthError> + Send + Sync {
+    fn token(&self) -> Result<AuthToken, AuthError> {
+        self()
+    }
+}
+
+/// A provider for tokens that expire, it calls ```fetch``` for a new token only
+/// once the cached one is about to expire.
+///
+/// # Examples
+/// ```
+/// # use firebase::*;
+/// use std::time::{Duration, SystemTime};
+///
+/// let provider = CachedToken::new(|| {
+///     // Sign in again, e.g. by exchanging a refresh token.
+///     Ok(ExpiringToken {
+///         token: AuthToken::Auth("fresh-id-token".to_string()),
+///         expires_at: SystemTime::now() + Duration::from_secs(3600),
+///     })
+/// });
+/// let fb = Firebase::with_token_provider("https://myfb.firebaseio.com", provider).unwrap();
+/// ```
+pub struct CachedToken<F> {
+    fetch: F,
+    cache: TokenCache,
+}
+
+impl<F> CachedToken<F> where F: Fn() -> Result<ExpiringToken, AuthError> + Send + Sync {
+    /// Creates a provider that refreshes tokens five minutes before they expire.
+    pub fn new(fetch: F) -> Self {
+        CachedToken {
+            fetch: fetch,
+            cache: TokenCache::new(),
+        }
+    }
+
+    /// Sets how long before it expires a token is refreshed.
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.cache.margin = margin;
+        self
+    }
+
+    /// Drops the cached token, so the next request fetches a new one.
+    pub fn invalidate(&self) {
+        self.cache.clear();
+    }
+}
+
+impl<F> TokenProvider for CachedToken<F> where F: Fn() -> Result<ExpiringToken, AuthError> + Send + Sync {
+    fn token(&self) -> Result<AuthToken, AuthError> {
+        self.cache.get_or_fetch(&self.fetch)
+    }
+}
+
+/// Holds on to an expiring token until it has to be refreshed.
+pub struct TokenCache {
+    margin: Duration,
+    cached: Mutex<Option<ExpiringToken>>,
+}
+
+impl TokenCache {
+    pub fn new() -> Self {
+        TokenCache {
+            margin: Duration::from_secs(REFRESH_MARGIN),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached token, calling ```fetch``` first if it is missing
+    /// or about to expire. The lock is held while fetching so concurrent
+    /// requests share a single refresh.
+    pub fn get_or_fetch<F>(&self, fetch: F) -> Result<AuthToken, AuthError>
+    where F: FnOnce() -> Result<ExpiringToken, AuthError> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some(ref token) = *cached {
+            if !token.expires_within(self.margin) {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let token = try!( fetch() );
+        *cached = Some(token.clone());
+        Ok(token.token)
+    }
+
+    pub fn clear(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
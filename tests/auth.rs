@@ -0,0 +1,323 @@
+extern crate firebase;
+extern crate ring;
+extern crate rustc_serialize;
+extern crate url;
+
+mod support;
+
+use firebase::*;
+use ring::hmac;
+use ring::signature;
+use ring::signature::KeyPair;
+use rustc_serialize::base64::FromBase64;
+use url::{form_urlencoded, Url};
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const KEY_FILE: &'static str = "tests/fixtures/service_account.json";
+const TOKEN_RESP: &'static str = "{\"access_token\":\"ya29.stub\",\"expires_in\":3600,\"token_type\":\"Bearer\"}";
+
+#[test]
+fn loads_key_file() {
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().expect("Should load key file");
+    assert_eq!(credentials.client_email(), "firebase-adminsdk-test@rifebass-test.iam.gserviceaccount.com");
+    assert_eq!(credentials.project_id(), Some("rifebass-test"));
+    assert_eq!(credentials.location(), TokenLocation::BearerHeader);
+    assert!(!format!("{:?}", credentials).contains("PRIVATE"));
+}
+
+#[test]
+fn rejects_bad_key_file() {
+    match ServiceAccountCredentials::from_json("{ \"private_key\": \"nope\" }") {
+        Err(AuthError::MissingField("client_email")) => {},
+        _ => panic!("Should've been missing the client email"),
+    }
+    match ServiceAccountCredentials::from_json("{ \"client_email\": \"a@b.c\", \"private_key\": \"nope\" }") {
+        Err(AuthError::InvalidPrivateKey) => {},
+        _ => panic!("Should've rejected the private key"),
+    }
+}
+
+#[test]
+fn exchanges_signed_jwt_for_token() {
+    let (base, requests) = support::serve(vec![support::response(200, TOKEN_RESP)]);
+    let uri = base + "/token";
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&uri);
+
+    assert_eq!(credentials.access_token().ok().unwrap(), "ya29.stub");
+
+    let request = requests.recv().unwrap();
+    assert_eq!(request.request_line(), "POST /token HTTP/1.1");
+    let form = form_urlencoded::parse(request.body.as_bytes());
+    assert!(form.contains(&("grant_type".to_string(),
+                            "urn:ietf:params:oauth:grant-type:jwt-bearer".to_string())));
+    let assertion = form.iter().find(|&&(ref k, _)| k == "assertion").expect("Should send an assertion");
+
+    let parts: Vec<&str> = assertion.1.split('.').collect();
+    assert_eq!(parts.len(), 3);
+    let header = Json::from_str(&String::from_utf8(parts[0].from_base64().unwrap()).unwrap()).unwrap();
+    let claims = Json::from_str(&String::from_utf8(parts[1].from_base64().unwrap()).unwrap()).unwrap();
+    assert_eq!(header.find("alg").and_then(|a| a.as_string()), Some("RS256"));
+    assert_eq!(header.find("kid").and_then(|a| a.as_string()), Some("0123456789abcdef0123456789abcdef01234567"));
+    assert_eq!(claims.find("aud").and_then(|a| a.as_string()), Some(&uri as &str));
+    assert_eq!(claims.find("iss").and_then(|a| a.as_string()),
+               Some("firebase-adminsdk-test@rifebass-test.iam.gserviceaccount.com"));
+    let iat = claims.find("iat").and_then(|a| a.as_u64()).unwrap();
+    let exp = claims.find("exp").and_then(|a| a.as_u64()).unwrap();
+    assert_eq!(exp - iat, 3600);
+
+    assert!(verify(&assertion.1));
+}
+
+#[test]
+fn caches_token_until_expiry() {
+    let (base, requests) = support::serve(vec![
+        support::response(200, TOKEN_RESP),
+        support::response(200, "{\"access_token\":\"ya29.second\",\"expires_in\":3600}"),
+    ]);
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(base + "/token"));
+
+    assert_eq!(credentials.access_token().ok().unwrap(), "ya29.stub");
+    assert_eq!(credentials.access_token().ok().unwrap(), "ya29.stub");
+    requests.recv().unwrap();
+    assert!(requests.try_recv().is_err());
+}
+
+#[test]
+fn refreshes_token_before_expiry() {
+    let (base, requests) = support::serve(vec![
+        support::response(200, "{\"access_token\":\"ya29.short\",\"expires_in\":60}"),
+        support::response(200, "{\"access_token\":\"ya29.fresh\",\"expires_in\":3600}"),
+    ]);
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(base + "/token"));
+
+    assert_eq!(credentials.access_token().ok().unwrap(), "ya29.short");
+    assert_eq!(credentials.access_token().ok().unwrap(), "ya29.fresh");
+    requests.recv().unwrap();
+    requests.recv().unwrap();
+}
+
+#[test]
+fn fetches_token_through_client() {
+    let (proxy, requests) = support::serve(vec![support::response(200, TOKEN_RESP)]);
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri("http://oauth.invalid/token")
+                                                .with_client(Client::new().proxy(&proxy));
+
+    assert_eq!(credentials.access_token().ok().unwrap(), "ya29.stub");
+    assert_eq!(requests.recv().unwrap().request_line(), "POST http://oauth.invalid/token HTTP/1.1");
+}
+
+#[test]
+fn times_out_token_request() {
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(support::hang() + "/token"))
+                                                .timeout(Duration::from_millis(300));
+
+    match credentials.access_token() {
+        Err(AuthError::NetworkErr(_)) => {},
+        other => panic!("Should've timed out, got {:?}", other),
+    }
+}
+
+#[test]
+fn reports_rejected_token_request() {
+    let (base, _requests) = support::serve(vec![
+        support::response(400, "{\"error\":\"invalid_grant\"}"),
+    ]);
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(base + "/token"));
+
+    match credentials.access_token() {
+        Err(AuthError::TokenRejected(resp)) => {
+            assert_eq!(resp.code, 400);
+            assert_eq!(resp.body, "{\"error\":\"invalid_grant\"}");
+        },
+        other => panic!("Should've been rejected, got {:?}", other),
+    }
+}
+
+#[test]
+fn requests_fail_without_token() {
+    let (base, _requests) = support::serve(vec![
+        support::response(401, "{\"error\":\"unauthorized_client\"}"),
+    ]);
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(base + "/token"));
+    let fb = Firebase::with_service_account("https://db.rifebass.com", credentials).ok().unwrap();
+
+    match fb.at("futurama").ok().unwrap().get() {
+        Err(ReqErr::AuthErr(AuthError::TokenRejected(resp))) => assert_eq!(resp.code, 401),
+        other => panic!("Should've failed to authenticate, got {:?}", other),
+    }
+}
+
+#[test]
+fn queues_writes_without_token() {
+    let journal = support::temp_path("queues_writes_without_token");
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(support::unreachable() + "/token"));
+    let fb = Firebase::from_url(&Url::parse(&support::unreachable()).unwrap()).ok().unwrap()
+                      .auth_with(credentials)
+                      .with_write_queue(WriteQueue::open(&journal).unwrap());
+
+    match fb.at("futurama").ok().unwrap().set("true") {
+        Err(ReqErr::Queued) => {},
+        other => panic!("Should've been queued, got {:?}", other),
+    }
+}
+
+#[test]
+fn serves_stale_reads_without_token() {
+    let (base, requests) = support::serve(vec![support::response(200, "{\"fry\":1025}")]);
+    let store = Arc::new(SnapshotStore::open(support::temp_path("serves_stale_reads_without_token")).unwrap());
+    let users = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap().at("users").ok().unwrap();
+    users.with_snapshot_store(store.clone()).get().ok().unwrap();
+    requests.recv().unwrap();
+
+    let credentials = ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap()
+                                                .token_uri(&(support::unreachable() + "/token"));
+    let res = users.auth_with(credentials).with_snapshot_store(store).get().ok().unwrap();
+    assert!(res.stale);
+    assert_eq!(res.body, "{\"fry\":1025}");
+}
+
+#[test]
+fn mints_custom_tokens() {
+    let credentials = Arc::new(ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap());
+    let minter = TokenMinter::new(credentials.clone());
+
+    let mut claims = BTreeMap::new();
+    claims.insert("premium".to_string(), Json::Boolean(true));
+    let token = minter.create_custom_token("uid-1", Some(&Json::Object(claims))).ok().unwrap();
+
+    let parts: Vec<&str> = token.split('.').collect();
+    assert_eq!(parts.len(), 3);
+    let claims = Json::from_str(&String::from_utf8(parts[1].from_base64().unwrap()).unwrap()).unwrap();
+    let email = "firebase-adminsdk-test@rifebass-test.iam.gserviceaccount.com";
+    assert_eq!(claims.find("iss").and_then(|c| c.as_string()), Some(email));
+    assert_eq!(claims.find("sub").and_then(|c| c.as_string()), Some(email));
+    assert_eq!(claims.find("aud").and_then(|c| c.as_string()),
+               Some("https://identitytoolkit.googleapis.com/google.identity.identitytoolkit.v1.IdentityToolkit"));
+    assert_eq!(claims.find("uid").and_then(|c| c.as_string()), Some("uid-1"));
+    assert_eq!(claims.find_path(&["claims", "premium"]), Some(&Json::Boolean(true)));
+    let iat = claims.find("iat").and_then(|a| a.as_u64()).unwrap();
+    let exp = claims.find("exp").and_then(|a| a.as_u64()).unwrap();
+    assert_eq!(exp - iat, 3600);
+    assert!(verify(&token));
+
+    let short = TokenMinter::new(credentials).lifetime(Duration::from_secs(600));
+    let token = short.create_custom_token("uid-2", None).ok().unwrap();
+    let claims = Json::from_str(&String::from_utf8(token.split('.').nth(1).unwrap().from_base64().unwrap()).unwrap()).unwrap();
+    assert_eq!(claims.find("claims"), None);
+    let iat = claims.find("iat").and_then(|a| a.as_u64()).unwrap();
+    let exp = claims.find("exp").and_then(|a| a.as_u64()).unwrap();
+    assert_eq!(exp - iat, 600);
+}
+
+#[test]
+fn rejects_invalid_custom_tokens() {
+    let minter = TokenMinter::new(ServiceAccountCredentials::from_file(KEY_FILE).ok().unwrap());
+
+    match minter.create_custom_token("", None) {
+        Err(AuthError::InvalidUid) => {},
+        other => panic!("Should've rejected the empty uid, got {:?}", other),
+    }
+    let long_uid: String = std::iter::repeat('u').take(129).collect();
+    match minter.create_custom_token(&long_uid, None) {
+        Err(AuthError::InvalidUid) => {},
+        other => panic!("Should've rejected the long uid, got {:?}", other),
+    }
+    match minter.create_custom_token("uid", Some(&Json::U64(3))) {
+        Err(AuthError::ClaimsNotObject) => {},
+        other => panic!("Should've rejected the claims, got {:?}", other),
+    }
+    let mut claims = BTreeMap::new();
+    claims.insert("sub".to_string(), Json::String("someone-else".to_string()));
+    match minter.create_custom_token("uid", Some(&Json::Object(claims))) {
+        Err(AuthError::ReservedClaim(claim)) => assert_eq!(claim, "sub"),
+        other => panic!("Should've rejected the reserved claim, got {:?}", other),
+    }
+}
+
+#[test]
+fn generates_legacy_tokens() {
+    let generator = LegacyTokenGenerator::new("deadbeaf");
+    let mut data = BTreeMap::new();
+    data.insert("uid".to_string(), Json::String("uid-1".to_string()));
+    data.insert("team".to_string(), Json::String("planet-express".to_string()));
+    let token = generator.create_token(Some(&Json::Object(data)), &LegacyTokenOptions {
+        expires:   Some(2000000000),
+        issued_at: Some(1000000000),
+        debug:     true,
+        .. LegacyTokenOptions::default()
+    }).ok().unwrap();
+
+    let parts: Vec<&str> = token.split('.').collect();
+    let header = Json::from_str(&String::from_utf8(parts[0].from_base64().unwrap()).unwrap()).unwrap();
+    let claims = Json::from_str(&String::from_utf8(parts[1].from_base64().unwrap()).unwrap()).unwrap();
+    assert_eq!(header.find("alg").and_then(|a| a.as_string()), Some("HS256"));
+    assert_eq!(claims.find("v"), Some(&Json::U64(0)));
+    assert_eq!(claims.find("iat"), Some(&Json::U64(1000000000)));
+    assert_eq!(claims.find("exp"), Some(&Json::U64(2000000000)));
+    assert_eq!(claims.find("debug"), Some(&Json::Boolean(true)));
+    assert_eq!(claims.find("admin"), None);
+    assert_eq!(claims.find_path(&["d", "uid"]).and_then(|u| u.as_string()), Some("uid-1"));
+    assert_eq!(claims.find_path(&["d", "team"]).and_then(|u| u.as_string()), Some("planet-express"));
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, b"deadbeaf");
+    let message = format!("{}.{}", parts[0], parts[1]);
+    assert!(hmac::verify(&key, message.as_bytes(), &parts[2].from_base64().unwrap()).is_ok());
+}
+
+#[test]
+fn legacy_tokens_need_uid_unless_admin() {
+    let generator = LegacyTokenGenerator::new("deadbeaf");
+    match generator.create_token(None, &LegacyTokenOptions::default()) {
+        Err(AuthError::InvalidUid) => {},
+        other => panic!("Should've needed a uid, got {:?}", other),
+    }
+    match generator.create_token(Some(&Json::String("uid".to_string())), &LegacyTokenOptions::default()) {
+        Err(AuthError::ClaimsNotObject) => {},
+        other => panic!("Should've needed an object, got {:?}", other),
+    }
+
+    let token = generator.create_token(None, &LegacyTokenOptions {
+        admin: true,
+        .. LegacyTokenOptions::default()
+    }).ok().expect("Admin tokens don't need a uid");
+    let claims = Json::from_str(&String::from_utf8(token.split('.').nth(1).unwrap().from_base64().unwrap()).unwrap()).unwrap();
+    assert_eq!(claims.find("admin"), Some(&Json::Boolean(true)));
+
+    let mut data = BTreeMap::new();
+    data.insert("uid".to_string(), Json::String("uid".to_string()));
+    data.insert("padding".to_string(), Json::String(std::iter::repeat('p').take(1024).collect()));
+    match generator.create_token(Some(&Json::Object(data)), &LegacyTokenOptions::default()) {
+        Err(AuthError::TokenTooLong) => {},
+        other => panic!("Should've been too long, got {:?}", other),
+    }
+}
+
+/// Checks the signature of a JWT against the public half of the key file.
+fn verify(jwt: &str) -> bool {
+    let key_file = Json::from_str(&read_fixture()).unwrap();
+    let pem = key_file.find("private_key").and_then(|k| k.as_string()).unwrap();
+    let der: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+    let key_pair = signature::RsaKeyPair::from_pkcs8(&der.from_base64().unwrap()).unwrap();
+    let public_key = signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256,
+                                                       key_pair.public_key().as_ref());
+    let (message, sig) = jwt.split_at(jwt.rfind('.').unwrap());
+    public_key.verify(message.as_bytes(), &sig[1..].from_base64().unwrap()).is_ok()
+}
+
+fn read_fixture() -> String {
+    use std::io::Read;
+    let mut contents = String::new();
+    std::fs::File::open(KEY_FILE).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
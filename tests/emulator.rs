@@ -0,0 +1,47 @@
+extern crate firebase;
+
+mod support;
+
+use firebase::*;
+
+use std::env;
+
+#[test]
+fn sends_namespace_and_owner() {
+    let (base, requests) = support::serve(vec![support::response(200, "null"),
+                                               support::response(200, "null")]);
+    let f = Firebase::emulator(&base, "rifebass").ok().unwrap();
+    let users = f.at("users").ok().unwrap();
+
+    users.get().ok().unwrap();
+    let request = requests.recv().unwrap();
+    assert_eq!(request.request_line(), "GET /users.json?ns=rifebass HTTP/1.1");
+    assert_eq!(request.header("Authorization"), Some("Bearer owner".to_string()));
+
+    users.limit_to_first(5).get().ok().unwrap();
+    let request = requests.recv().unwrap();
+    assert!(request.request_line().contains("ns=rifebass"));
+    assert!(request.request_line().contains("limitToFirst=5"));
+}
+
+// Everything reading the environment is kept in one test,
+// so no other test sees the variable.
+#[test]
+fn detects_emulator_host() {
+    let (base, requests) = support::serve(vec![support::response(200, "null")]);
+    env::set_var("FIREBASE_DATABASE_EMULATOR_HOST", base.trim_left_matches("http://"));
+
+    let f = Firebase::authed("https://rifebass.firebaseio.com/users", "deadbeaf").ok().unwrap();
+    f.at("bender").ok().unwrap().get().ok().unwrap();
+    let request = requests.recv().unwrap();
+    assert_eq!(request.request_line(), "GET /users/bender.json?ns=rifebass HTTP/1.1");
+    assert_eq!(request.header("Authorization"), Some("Bearer owner".to_string()));
+
+    match Firebase::new("https://localhost/") {
+        Err(ParseError::UrlHasNoNamespace) => {},
+        _ => panic!("Should've needed a namespace"),
+    }
+
+    env::remove_var("FIREBASE_DATABASE_EMULATOR_HOST");
+    assert!(Firebase::new("http://localhost:9000/?ns=rifebass").is_err());
+}
@@ -0,0 +1,305 @@
+#![cfg(feature = "nonblocking")]
+
+extern crate firebase;
+extern crate futures_util;
+extern crate tokio;
+extern crate url;
+
+mod support;
+
+use firebase::*;
+use firebase::nonblocking::Event;
+use futures_util::StreamExt;
+use futures_util::future;
+use tokio::runtime::Runtime;
+use url::Url;
+
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn event_stream(events: &str) -> String {
+    support::response(200, events).replace("application/json", "text/event-stream")
+}
+
+#[test]
+fn makes_requests() {
+    let (base, requests) = support::serve(vec![support::response(200, "{\"name\":\"-K1\"}"),
+                                               support::response(200, "null")]);
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap()
+                     .auth_with(AuthToken::Bearer("deadbeaf".to_string()))
+                     .nonblocking();
+    let users = f.at("users").ok().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let res = runtime.block_on(users.push("{\"name\":\"fry\"}")).ok().unwrap();
+    assert_eq!(res.body, "{\"name\":\"-K1\"}");
+    let request = requests.recv().unwrap();
+    assert_eq!(request.request_line(), "POST /users.json HTTP/1.1");
+    assert_eq!(request.header("Authorization"), Some("Bearer deadbeaf".to_string()));
+    assert_eq!(request.body, "{\"name\":\"fry\"}");
+
+    runtime.block_on(users.blocking().limit_to_first(1).nonblocking().get()).ok().unwrap();
+    assert_eq!(requests.recv().unwrap().request_line(), "GET /users.json?limitToFirst=1 HTTP/1.1");
+}
+
+#[test]
+fn retries_and_times_out() {
+    let (base, requests) = support::serve(vec![support::response(503, "null"),
+                                               support::response(200, "true")]);
+    let policy = RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(1));
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap().retry_policy(policy);
+    let runtime = Runtime::new().unwrap();
+
+    assert_eq!(runtime.block_on(f.nonblocking().set("true")).ok().unwrap().code, 200);
+    for _ in 0..2 {
+        assert_eq!(requests.recv().unwrap().request_line(), "PUT /.json HTTP/1.1");
+    }
+
+    let hung = Firebase::from_url(&Url::parse(&support::hang()).unwrap()).ok().unwrap()
+                        .request_timeout(Duration::from_millis(100));
+    match runtime.block_on(hung.nonblocking().get()) {
+        Err(ReqErr::Timeout) => {},
+        other => panic!("Should've timed out, got {:?}", other),
+    }
+}
+
+#[test]
+fn gives_up_stalled_requests_by_default() {
+    let f = Firebase::from_url(&Url::parse(&support::hang()).unwrap()).ok().unwrap().nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    let started = Instant::now();
+    match runtime.block_on(f.get()) {
+        Err(ReqErr::Timeout) => {},
+        other => panic!("Should've timed out, got {:?}", other),
+    }
+    assert!(started.elapsed() >= Duration::from_secs(29));
+}
+
+#[test]
+fn streams_events() {
+    let events = "event: put\ndata: {\"path\":\"/\",\"data\":{\"a\":1}}\n\n\
+                  event: keep-alive\ndata: null\n\n\
+                  event: patch\ndata: {\"path\":\"/b\",\"data\":{\"c\":true}}\n\n\
+                  event: cancel\ndata: \"permission denied\"\n\n";
+    let (base, requests) = support::serve(vec![event_stream(events)]);
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap().nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    let events: Vec<_> = runtime.block_on(f.at("users").ok().unwrap().listen().collect::<Vec<_>>());
+    let events: Vec<Event> = events.into_iter().map(|e| e.ok().unwrap()).collect();
+    assert_eq!(events, vec![
+        Event::Put(DbPath::new("/"), Json::from_str("{\"a\":1}").unwrap()),
+        Event::KeepAlive,
+        Event::Patch(DbPath::new("/b"), Json::from_str("{\"c\":true}").unwrap()),
+        Event::Cancel("permission denied".to_string()),
+    ]);
+    let request = requests.recv().unwrap();
+    assert_eq!(request.request_line(), "GET /users.json HTTP/1.1");
+    assert_eq!(request.header("Accept"), Some("text/event-stream".to_string()));
+}
+
+#[test]
+fn saves_listened_data() {
+    let events = "event: put\ndata: {\"path\":\"/\",\"data\":{\"fry\":{\"age\":1025},\"leela\":{\"age\":25}}}\n\n\
+                  event: patch\ndata: {\"path\":\"/fry\",\"data\":{\"job\":\"delivery\",\"age\":1026}}\n\n\
+                  event: put\ndata: {\"path\":\"/leela/age\",\"data\":null}\n\n\
+                  event: patch\ndata: {\"path\":\"/fry/reports\",\"data\":{\"report.json\":1}}\n\n\
+                  event: put\ndata: {\"path\":\"/fry/log.json\",\"data\":true}\n\n";
+    let (base, _) = support::serve(vec![event_stream(events)]);
+    let store = Arc::new(SnapshotStore::open(support::temp_path("saves_listened_data")).unwrap());
+    let users = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap()
+                         .at("users").ok().unwrap()
+                         .with_snapshot_store(store.clone())
+                         .nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    let events = runtime.block_on(users.listen().collect::<Vec<_>>());
+    assert_eq!(events.len(), 5);
+    match events[4] {
+        Ok(Event::Put(ref path, _)) => assert_eq!(path.segments(), &["fry".to_string(), "log.json".to_string()]),
+        ref other => panic!("Should've been a put, got {:?}", other),
+    }
+    assert_eq!(store.len(), 1);
+
+    // The server closes the connection without an answer.
+    let res = runtime.block_on(users.get()).ok().unwrap();
+    assert!(res.stale);
+    assert_eq!(res.json().unwrap(), Json::from_str("{\"fry\":{\"age\":1026,\"job\":\"delivery\",\
+                                                             \"log.json\":true,\"reports\":{\"report.json\":1}}}").unwrap());
+}
+
+#[test]
+fn follows_redirects_and_reports_refusals() {
+    let (moved, moved_requests) = support::serve(vec![event_stream("event: put\ndata: {\"path\":\"/\",\"data\":null}\n\n")]);
+    let redirect = support::with_header(support::response(307, ""), "Location",
+                                        &format!("{}/moved.json?auth=deadbeaf", moved));
+    let (base, requests) = support::serve(vec![redirect, support::response(401, "{\"error\":\"Permission denied\"}")]);
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap()
+                     .auth_with(AuthToken::Auth("deadbeaf".to_string()))
+                     .nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    let mut events = f.at("users").ok().unwrap().listen();
+    let event = runtime.block_on(events.next()).unwrap().ok().unwrap();
+    assert_eq!(event, Event::Put(DbPath::new("/"), Json::Null));
+    assert_eq!(requests.recv().unwrap().request_line(), "GET /users.json?auth=deadbeaf HTTP/1.1");
+    assert_eq!(moved_requests.recv().unwrap().request_line(), "GET /moved.json?auth=deadbeaf HTTP/1.1");
+
+    match runtime.block_on(f.listen().next()) {
+        Some(Err(ReqErr::Refused(res))) => assert_eq!(res.code, 401),
+        other => panic!("Should've been refused, got {:?}", other),
+    }
+}
+
+#[test]
+fn cancels_requests_and_listeners() {
+    let token = CancelToken::new();
+    let f = Firebase::from_url(&Url::parse(&support::hang()).unwrap()).ok().unwrap()
+                     .cancel_with(&token)
+                     .nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    let cancel = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        cancel.cancel();
+    });
+    match runtime.block_on(f.get()) {
+        Err(ReqErr::Cancelled) => {},
+        other => panic!("Should've been cancelled, got {:?}", other),
+    }
+
+    let mut events = f.listen();
+    match runtime.block_on(events.next()) {
+        Some(Err(ReqErr::Cancelled)) => {},
+        other => panic!("Should've been cancelled, got {:?}", other),
+    }
+    assert!(runtime.block_on(events.next()).is_none());
+}
+
+#[test]
+fn finishes_cancelled_writes() {
+    let token = CancelToken::new();
+    let f = Firebase::from_url(&Url::parse(&support::hang()).unwrap()).ok().unwrap()
+                     .request_timeout(Duration::from_millis(500))
+                     .cancel_with(&token)
+                     .nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    let cancel = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        cancel.cancel();
+    });
+    match runtime.block_on(f.set("true")) {
+        Err(ReqErr::Timeout) => {},
+        other => panic!("Should've timed out, got {:?}", other),
+    }
+    // Writes made after the token was cancelled are never sent.
+    match runtime.block_on(f.set("true")) {
+        Err(ReqErr::Cancelled) => {},
+        other => panic!("Should've been cancelled, got {:?}", other),
+    }
+}
+
+#[test]
+fn refuses_unsupported_client_options() {
+    let runtime = Runtime::new().unwrap();
+    let f = Firebase::from_url(&Url::parse("http://db.invalid/").unwrap()).ok().unwrap();
+
+    let proxied = f.with_client(Client::new().proxy("http://127.0.0.1:1")).nonblocking();
+    match runtime.block_on(proxied.get()) {
+        Err(ReqErr::Unsupported(_)) => {},
+        other => panic!("Should've refused the proxy, got {:?}", other),
+    }
+
+    let untrusted = f.with_client(Client::new().ca_bundle("/does/not/exist.pem")).nonblocking();
+    match runtime.block_on(untrusted.listen().next()) {
+        Some(Err(ReqErr::TransportErr(_))) => {},
+        other => panic!("Should've failed to load the bundle, got {:?}", other),
+    }
+
+    let uncertified = f.with_client(Client::new().client_cert("/does/not/exist.pem", "/does/not/exist.key"))
+                       .nonblocking();
+    match runtime.block_on(uncertified.get()) {
+        Err(ReqErr::TransportErr(ref e)) if e.contains("client certificate") => {},
+        other => panic!("Should've failed to load the certificate, got {:?}", other),
+    }
+}
+
+#[test]
+fn uses_tls_options_curl_lacks() {
+    let (base, requests) = support::serve(vec![support::response(200, "true")]);
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap()
+                     .with_client(Client::new().min_tls_version(TlsVersion::Tls13))
+                     .nonblocking();
+    let runtime = Runtime::new().unwrap();
+
+    assert_eq!(runtime.block_on(f.get()).ok().unwrap().body, "true");
+    assert_eq!(requests.recv().unwrap().request_line(), "GET /.json HTTP/1.1");
+}
+
+#[test]
+fn streams_large_responses() {
+    let big = format!("\"{}\"", "x".repeat(4 * 1024 * 1024));
+    let (base, requests) = support::serve(vec![support::response(200, &big),
+                                               support::response(404, "null")]);
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap();
+
+    let mut reader = f.at("export").ok().unwrap().get_reader().ok().unwrap();
+    assert_eq!(reader.code, 200);
+    let mut body = Vec::new();
+    io::copy(&mut reader, &mut body).unwrap();
+    assert!(body == big.as_bytes());
+    assert_eq!(requests.recv().unwrap().request_line(), "GET /export.json HTTP/1.1");
+
+    let mut reader = f.shallow(true).get_reader().ok().unwrap();
+    assert_eq!(reader.code, 404);
+    let mut body = String::new();
+    reader.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "null");
+    assert_eq!(requests.recv().unwrap().request_line(), "GET /.json?shallow=true HTTP/1.1");
+}
+
+#[test]
+fn reader_rejects_proxies() {
+    struct Unreachable;
+
+    impl TokenProvider for Unreachable {
+        fn token(&self) -> Result<AuthToken, AuthError> {
+            panic!("Should've failed before fetching a token");
+        }
+    }
+
+    let f = Firebase::from_url(&Url::parse("http://db.invalid/").unwrap()).ok().unwrap()
+                     .with_client(Client::new().proxy("http://127.0.0.1:1"))
+                     .auth_with(Unreachable);
+
+    match f.get_reader() {
+        Err(ReqErr::Unsupported(_)) => {},
+        other => panic!("Should've refused the proxy, got {:?}", other.map(|r| r.code)),
+    }
+}
+
+#[test]
+fn reader_fails_inside_runtime() {
+    let (base, _requests) = support::serve(vec![support::response(200, "null")]);
+    let f = Firebase::from_url(&Url::parse(&base).unwrap()).ok().unwrap();
+
+    let runtime = Runtime::new().unwrap();
+    let result = runtime.block_on(future::lazy(|_| f.get_reader().map(|r| r.code)));
+    match result {
+        Err(ReqErr::Unsupported(_)) => {},
+        other => panic!("Should've failed inside the runtime, got {:?}", other),
+    }
+
+    let mut reader = f.get_reader().ok().unwrap();
+    let read = runtime.block_on(future::lazy(move |_| {
+        let mut body = String::new();
+        reader.read_to_string(&mut body)
+    }));
+    assert!(read.is_err());
+}
@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// A raw HTTP request received by the stub server.
+pub struct Request {
+    pub head: String,
+    pub body: String,
+    /// Counts the connections made to the server, starting at 0.
+    pub connection: usize,
+}
+
+impl Request {
+    /// Returns the value of the first header called ```name```, ignoring case.
+    pub fn header(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase() + ":";
+        self.head.lines()
+            .find(|line| line.to_lowercase().starts_with(&name))
+            .map(|line| line[name.len()..].trim().to_string())
+    }
+
+    /// The request line, e.g. ```GET /users.json HTTP/1.1```.
+    pub fn request_line(&self) -> &str {
+        self.head.lines().next().unwrap_or("")
+    }
+}
+
+/// Starts a local HTTP server that answers one request with each of
+/// ```responses``` in order. Returns the base url of the server and a
+/// receiver for the requests it got. Connections are closed after every
+/// response, unless it was made with ```keep_alive```.
+pub fn serve<R>(responses: Vec<R>) -> (String, Receiver<Request>)
+where R: Into<Vec<u8>> {
+    serve_at("http://127.0.0.1:0", responses)
+}
+
+/// Like ```serve```, but on the port of ```base```, e.g. one
+/// that was ```unreachable``` before.
+pub fn serve_at<R>(base: &str, responses: Vec<R>) -> (String, Receiver<Request>)
+where R: Into<Vec<u8>> {
+    let listener = TcpListener::bind(base.trim_left_matches("http://")).unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = channel();
+
+    let responses: Vec<Vec<u8>> = responses.into_iter().map(|r| r.into()).collect();
+    let responses = Arc::new(Mutex::new(responses.into_iter()));
+
+    // Every connection gets its own thread, so a client can keep
+    // a connection open while it makes new ones.
+    thread::spawn(move || {
+        for (connection, stream) in listener.incoming().enumerate() {
+            let mut stream = match stream {
+                Ok(s)  => s,
+                Err(_) => return,
+            };
+            let responses = responses.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                while let Some(mut request) = read_request(&mut stream) {
+                    let response = match responses.lock().unwrap().next() {
+                        Some(r) => r,
+                        None    => return,
+                    };
+                    request.connection = connection;
+                    let _ = stream.write_all(&response);
+                    let close = response.windows(17).any(|w| w == b"Connection: close");
+                    if tx.send(request).is_err() || close {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    (base, rx)
+}
+
+/// Starts a server that accepts connections but never answers them.
+pub fn hang() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        let mut open = Vec::new();
+        for stream in listener.incoming() {
+            open.push(stream);
+        }
+    });
+
+    base
+}
+
+/// Returns the base url of a local port nothing listens on,
+/// so requests to it fail to connect.
+pub fn unreachable() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// Returns a path in the temporary directory that no file is at,
+/// unique to ```name``` and this process.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("firebase-{}-{}", process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Builds a complete HTTP response with a JSON body.
+pub fn response(code: u32, body: &str) -> String {
+    format!("HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code, body.len(), body)
+}
+
+/// Builds a complete HTTP response with a compressed body.
+pub fn encoded_response(code: u32, encoding: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\n\
+                                Content-Encoding: {}\r\nContent-Length: {}\r\n\
+                                Connection: close\r\n\r\n", code, encoding, body.len()).into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// Keeps the connection open after a response built by ```response```.
+pub fn keep_alive(response: String) -> String {
+    response.replace("Connection: close", "Connection: keep-alive")
+}
+
+/// Adds a header to a response built by ```response```.
+pub fn with_header(response: String, name: &str, value: &str) -> String {
+    response.replacen("\r\n", &format!("\r\n{}: {}\r\n", name, value), 1)
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut head = String::new();
+    let mut content_length = 0;
+    let mut expect_continue = false;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        let lower = line.to_lowercase();
+        if lower.starts_with("content-length:") {
+            content_length = lower[15..].trim().parse().unwrap_or(0);
+        }
+        if lower.starts_with("expect:") && lower.contains("100-continue") {
+            expect_continue = true;
+        }
+        head.push_str(&line);
+    }
+
+    if head.is_empty() {
+        return None;
+    }
+    if expect_continue {
+        let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+    let mut body = vec![0; content_length];
+    let _ = reader.read_exact(&mut body);
+
+    Some(Request {
+        head: head,
+        body: String::from_utf8_lossy(&body).into_owned(),
+        connection: 0,
+    })
+}